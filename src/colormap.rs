@@ -0,0 +1,48 @@
+use crate::models::BMPixel;
+
+const fn channel(value: i32) -> u32 {
+    if value < 0 {
+        0
+    } else if value > 255 {
+        255
+    } else {
+        value as u32
+    }
+}
+
+enum Kind {
+    Hot,
+    Jet,
+}
+
+const fn build(kind: Kind) -> [BMPixel; 256] {
+    let mut colormap = [BMPixel::EMPTY; 256];
+    let mut i = 0;
+    while i < 256 {
+        let (r, g, b) = match kind {
+            Kind::Hot => hot(i as i32),
+            Kind::Jet => jet(i as i32),
+        };
+        colormap[i] = BMPixel(channel(r) << 16 | channel(g) << 8 | channel(b));
+        i += 1;
+    }
+    colormap
+}
+
+const fn hot(i: i32) -> (i32, i32, i32) {
+    (3 * i, 3 * i - 255, 3 * i - 510)
+}
+
+const fn jet(i: i32) -> (i32, i32, i32) {
+    (
+        382 - (4 * i - 765).abs(),
+        382 - (4 * i - 510).abs(),
+        382 - (4 * i - 255).abs(),
+    )
+}
+
+/// Black through red and yellow to white.
+pub const HOT: [BMPixel; 256] = build(Kind::Hot);
+
+/// Blue through cyan, yellow, and red, as in MATLAB's `jet`.
+pub const JET: [BMPixel; 256] = build(Kind::Jet);
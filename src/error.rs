@@ -0,0 +1,117 @@
+use std::fmt;
+
+/// Errors returned by fallible bitmap operations.
+#[derive(Debug)]
+pub enum BmpError {
+    /// The underlying reader or writer failed.
+    Io(std::io::Error),
+    /// The data does not start with a BMP signature.
+    NotBmp,
+    /// Uncompressed images must be 1-, 4-, 8-, 24- or 32-bit, and RLE8 images 8-bit.
+    UnsupportedBitCount(u16),
+    /// Only uncompressed, RLE8 and BGRA bitfield images are supported.
+    UnsupportedCompression(u32),
+    /// The pixel data ends before the image does.
+    TruncatedPixelData,
+    /// A raw buffer does not hold exactly one entry per pixel.
+    InvalidLength { expected: usize, found: usize },
+    /// A character in an ASCII image has no palette entry.
+    UnknownSymbol(char),
+    /// A bounding box is empty or extends past the image.
+    InvalidBounds,
+    /// The operation only works on square images.
+    NotSquare { width: usize, height: usize },
+    /// Two images that must share dimensions do not.
+    DimensionMismatch {
+        expected: (usize, usize),
+        found: (usize, usize),
+    },
+    /// The header's `biSizeImage` is larger than both the image and the file need.
+    SizeMismatch { declared: usize, expected: usize },
+    /// The image has more distinct colors than an indexed format can hold.
+    TooManyColors { limit: usize },
+    /// A `.cube` LUT file has a line that cannot be parsed, counting from 1.
+    InvalidCube { line: usize },
+    /// The header declares more pixels than the decoder is willing to allocate.
+    ImageTooLarge { width: usize, height: usize },
+}
+
+impl fmt::Display for BmpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BmpError::Io(err) => write!(f, "I/O error: {err}"),
+            BmpError::NotBmp => write!(f, "not a BMP file"),
+            BmpError::UnsupportedBitCount(bits) => {
+                write!(f, "unsupported bit count: {bits}")
+            }
+            BmpError::UnsupportedCompression(compression) => {
+                write!(f, "unsupported compression: {compression}")
+            }
+            BmpError::TruncatedPixelData => write!(f, "pixel data is truncated"),
+            BmpError::InvalidLength { expected, found } => {
+                write!(
+                    f,
+                    "invalid buffer length: expected {expected}, found {found}"
+                )
+            }
+            BmpError::UnknownSymbol(symbol) => write!(f, "no palette entry for {symbol:?}"),
+            BmpError::InvalidBounds => write!(f, "bounding box is empty or outside the image"),
+            BmpError::NotSquare { width, height } => {
+                write!(f, "image must be square, found {width}x{height}")
+            }
+            BmpError::DimensionMismatch { expected, found } => write!(
+                f,
+                "dimension mismatch: expected {}x{}, found {}x{}",
+                expected.0, expected.1, found.0, found.1
+            ),
+            BmpError::SizeMismatch { declared, expected } => write!(
+                f,
+                "header declares {declared} bytes of pixel data, expected {expected}"
+            ),
+            BmpError::TooManyColors { limit } => write!(
+                f,
+                "image has more than {limit} colors; write it as 24-bit instead"
+            ),
+            BmpError::InvalidCube { line } => write!(f, "invalid .cube LUT at line {line}"),
+            BmpError::ImageTooLarge { width, height } => {
+                write!(f, "image of {width}x{height} pixels is too large to decode")
+            }
+        }
+    }
+}
+
+impl std::error::Error for BmpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BmpError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for BmpError {
+    fn from(err: std::io::Error) -> Self {
+        BmpError::Io(err)
+    }
+}
+
+/// A pixel coordinate outside of the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutOfBounds {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl fmt::Display for OutOfBounds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "pixel ({}, {}) is outside of the {}x{} image",
+            self.x, self.y, self.width, self.height
+        )
+    }
+}
+
+impl std::error::Error for OutOfBounds {}
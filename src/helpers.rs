@@ -8,3 +8,40 @@ pub(crate) fn calculate_row_length(width: usize) -> usize {
 pub(crate) fn calculate_image_size(width: usize, height: usize) -> usize {
     calculate_row_length(width) * height
 }
+
+/// Row length in bytes for any bit depth, padded to 4 bytes.
+pub(crate) fn calculate_stride(width: usize, bit_count: u16) -> usize {
+    (width * bit_count as usize).div_ceil(32) * 4
+}
+
+/// Size in bytes of `height` padded rows, or `None` if a header's dimensions overflow `usize`.
+pub(crate) fn checked_image_size(width: usize, height: usize, bit_count: u16) -> Option<usize> {
+    let bits = width.checked_mul(bit_count as usize)?;
+    bits.div_ceil(32).checked_mul(4)?.checked_mul(height)
+}
+
+/// SplitMix64, a tiny deterministic PRNG so procedural output is reproducible without `rand`.
+pub(crate) struct SplitMix64(pub(crate) u64);
+
+impl SplitMix64 {
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// A normalized 1D Gaussian kernel reaching out to three standard deviations.
+pub(crate) fn gaussian_kernel(sigma: f32) -> Vec<f32> {
+    if sigma <= 0.0 {
+        return vec![1.0];
+    }
+    let radius = (3.0 * sigma).ceil() as isize;
+    let weights: Vec<f32> = (-radius..=radius)
+        .map(|i| (-((i * i) as f32) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f32 = weights.iter().sum();
+    weights.into_iter().map(|weight| weight / total).collect()
+}
@@ -0,0 +1,14 @@
+pub mod colormap;
+mod error;
+mod font;
+mod helpers;
+mod lut;
+mod models;
+mod repr;
+pub use error::{BmpError, OutOfBounds};
+pub use font::measure_text;
+pub use lut::Lut3d;
+pub use models::{
+    hamming_distance, BMPixel, BlendMode, Bmp, BoundingBox, ChannelStats, ChannelSummary, Edge,
+    Orientation, SubImage,
+};
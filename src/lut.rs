@@ -0,0 +1,105 @@
+use std::path::Path;
+
+use crate::{error::BmpError, models::BMPixel};
+
+/// An N x N x N color lookup table with output colors in `0.0..=1.0`, stored like a `.cube`
+/// file: red varies fastest, then green, then blue.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lut3d {
+    size: usize,
+    table: Vec<[f32; 3]>,
+}
+
+impl Lut3d {
+    /// Builds a LUT from `size`³ entries in `.cube` order.
+    pub fn from_table(size: usize, table: Vec<[f32; 3]>) -> Result<Lut3d, BmpError> {
+        let expected = size.pow(3);
+        if size == 0 || table.len() != expected {
+            return Err(BmpError::InvalidLength {
+                expected,
+                found: table.len(),
+            });
+        }
+        Ok(Lut3d { size, table })
+    }
+
+    /// Loads an Adobe `.cube` 3D LUT. `TITLE` and `DOMAIN_*` lines are ignored, so inputs are
+    /// taken to span `0.0..=1.0`.
+    pub fn from_cube_file(path: &Path) -> Result<Lut3d, BmpError> {
+        Self::parse_cube(&std::fs::read_to_string(path)?)
+    }
+
+    fn parse_cube(text: &str) -> Result<Lut3d, BmpError> {
+        let mut size = None;
+        let mut table = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let invalid = || BmpError::InvalidCube { line: index + 1 };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+                ["LUT_3D_SIZE", n] if size.is_none() => {
+                    size = Some(n.parse::<usize>().map_err(|_| invalid())?);
+                }
+                ["TITLE", ..] | ["DOMAIN_MIN", ..] | ["DOMAIN_MAX", ..] => {}
+                // Entries are only meaningful once the size is known
+                [r, g, b] if size.is_some() => {
+                    let parse = |value: &str| value.parse::<f32>().map_err(|_| invalid());
+                    table.push([parse(r)?, parse(g)?, parse(b)?]);
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        let size = size.ok_or(BmpError::InvalidCube {
+            line: text.lines().count() + 1,
+        })?;
+        Self::from_table(size, table)
+    }
+
+    /// A LUT of the given size that maps every color to itself.
+    pub fn identity(size: usize) -> Lut3d {
+        let size = size.max(2);
+        let step = |i: usize| i as f32 / (size - 1) as f32;
+        let mut table = Vec::with_capacity(size.pow(3));
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    table.push([step(r), step(g), step(b)]);
+                }
+            }
+        }
+        Lut3d { size, table }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Looks up `pixel` by trilinear interpolation between the eight surrounding entries,
+    /// keeping its alpha.
+    pub(crate) fn apply(&self, pixel: BMPixel) -> BMPixel {
+        let n = self.size;
+        let locate = |channel: u8| {
+            let position = channel as f32 / 255.0 * (n - 1) as f32;
+            let low = (position.floor() as usize).min(n - 1);
+            (low, (low + 1).min(n - 1), position - low as f32)
+        };
+        let (r0, r1, fr) = locate(pixel.red());
+        let (g0, g1, fg) = locate(pixel.green());
+        let (b0, b1, fb) = locate(pixel.blue());
+
+        let entry = |r: usize, g: usize, b: usize| self.table[(b * n + g) * n + r];
+        let lerp = |a: [f32; 3], b: [f32; 3], t: f32| [0, 1, 2].map(|c| a[c] + (b[c] - a[c]) * t);
+        let plane = |b: usize| {
+            lerp(
+                lerp(entry(r0, g0, b), entry(r1, g0, b), fr),
+                lerp(entry(r0, g1, b), entry(r1, g1, b), fr),
+                fg,
+            )
+        };
+        let [r, g, b] = lerp(plane(b0), plane(b1), fb)
+            .map(|value| (value.clamp(0.0, 1.0) * 255.0).round() as u8);
+        BMPixel::from_rgba(r, g, b, pixel.alpha())
+    }
+}
@@ -1,12 +1,24 @@
 use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
     fs::File,
-    io::{Read, Seek, SeekFrom, Write},
+    io::{BufReader, ErrorKind, Read, Write},
+    ops::{Index, IndexMut},
     path::Path,
 };
 
-use crate::repr::{FileHeader, InfoHeader, Ode5Bmp};
+use crate::{
+    error::{BmpError, OutOfBounds},
+    font,
+    helpers::{calculate_stride, checked_image_size, gaussian_kernel, SplitMix64},
+    lut::Lut3d,
+    repr::{
+        decode_palette, decode_rle8, encode_row, FileHeader, InfoHeader, Ode5Bmp, BGRA_MASKS,
+        BI_BITFIELDS, BI_RLE8,
+    },
+};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct BMPixel(pub u32);
 
 impl BMPixel {
@@ -23,8 +35,124 @@ impl BMPixel {
     pub const fn blue(&self) -> u8 {
         (self.0 & 0x00_00ff) as u8
     }
+
+    /// Reads the alpha channel from bits 24..32.
+    ///
+    /// ```
+    /// use ode5bmp::BMPixel;
+    ///
+    /// let pixel = BMPixel::from_rgba(1, 2, 3, 4);
+    /// assert_eq!(pixel.alpha(), 4);
+    /// assert_eq!((pixel.red(), pixel.green(), pixel.blue()), (1, 2, 3));
+    /// ```
+    pub const fn alpha(&self) -> u8 {
+        (self.0 >> 24) as u8
+    }
+
+    pub const fn from_rgba(r: u8, g: u8, b: u8, a: u8) -> BMPixel {
+        BMPixel((a as u32) << 24 | (r as u32) << 16 | (g as u32) << 8 | b as u32)
+    }
+
+    /// Builds a fully opaque pixel.
+    ///
+    /// ```
+    /// use ode5bmp::BMPixel;
+    ///
+    /// assert_eq!(BMPixel::from_rgb(0x12, 0x34, 0x56), BMPixel(0xff12_3456));
+    /// ```
+    pub const fn from_rgb(r: u8, g: u8, b: u8) -> BMPixel {
+        BMPixel::from_rgba(r, g, b, 0xff)
+    }
+
+    /// Returns the same color with its alpha replaced by `a`.
+    pub const fn with_alpha(self, a: u8) -> BMPixel {
+        BMPixel((self.0 & 0x00ff_ffff) | (a as u32) << 24)
+    }
+
+    /// Returns the entry of `palette` closest in RGB space, or `None` for an empty palette.
+    pub fn nearest_color(&self, palette: &[BMPixel]) -> Option<BMPixel> {
+        palette.iter().copied().min_by_key(|candidate| {
+            let dr = self.red() as i32 - candidate.red() as i32;
+            let dg = self.green() as i32 - candidate.green() as i32;
+            let db = self.blue() as i32 - candidate.blue() as i32;
+            dr * dr + dg * dg + db * db
+        })
+    }
+
+    /// Rec. 601 luma, using integer weights that sum to 256.
+    const fn luminance(&self) -> u8 {
+        ((77 * self.red() as u32 + 150 * self.green() as u32 + 29 * self.blue() as u32) >> 8) as u8
+    }
+
+    /// Converts to hue in degrees, saturation, and value, the latter two in 0..=1.
+    fn to_hsv(self) -> (f32, f32, f32) {
+        let (r, g, b) = (
+            self.red() as f32 / 255.0,
+            self.green() as f32 / 255.0,
+            self.blue() as f32 / 255.0,
+        );
+        let max = r.max(g).max(b);
+        let delta = max - r.min(g).min(b);
+        let hue = if delta == 0.0 {
+            0.0
+        } else if max == r {
+            60.0 * ((g - b) / delta).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / delta + 2.0)
+        } else {
+            60.0 * ((r - g) / delta + 4.0)
+        };
+        let saturation = if max == 0.0 { 0.0 } else { delta / max };
+        (hue, saturation, max)
+    }
+
+    /// Inverse of [`BMPixel::to_hsv`], keeping the top byte of `self`.
+    fn with_hsv(self, hue: f32, saturation: f32, value: f32) -> BMPixel {
+        let chroma = value * saturation;
+        let sector = hue.rem_euclid(360.0) / 60.0;
+        let x = chroma * (1.0 - (sector.rem_euclid(2.0) - 1.0).abs());
+        let (r, g, b) = match sector as u32 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        let m = value - chroma;
+        let channel = |c: f32| ((c + m) * 255.0).round().clamp(0.0, 255.0) as u32;
+        BMPixel((self.0 & 0xff00_0000) | channel(r) << 16 | channel(g) << 8 | channel(b))
+    }
+
+    /// Multiplies each color channel by `factor`, clamping and leaving the top byte untouched.
+    fn scaled(self, factor: f32) -> BMPixel {
+        let scale = |channel: u8| (channel as f32 * factor).round().clamp(0.0, 255.0) as u32;
+        BMPixel(
+            (self.0 & 0xff00_0000)
+                | (scale(self.red()) << 16)
+                | (scale(self.green()) << 8)
+                | scale(self.blue()),
+        )
+    }
+    /// Linearly interpolates every byte, alpha included, from `self` at `t = 0` to `other` at
+    /// `t = 1`.
+    pub fn blend(self, other: BMPixel, t: f32) -> BMPixel {
+        let t = t.clamp(0.0, 1.0);
+        let (a, b) = (self.0.to_le_bytes(), other.0.to_le_bytes());
+        BMPixel(u32::from_le_bytes(std::array::from_fn(|i| {
+            (a[i] as f32 + (b[i] as f32 - a[i] as f32) * t).round() as u8
+        })))
+    }
+}
+
+impl std::fmt::Display for BMPixel {
+    /// Formats the color as `#RRGGBB`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{:06X}", self.0 & 0x00ff_ffff)
+    }
 }
 
+/// An image in row-major order with `(0, 0)` at the top-left, whatever the row order on disk.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Bmp {
     pub width: usize,
@@ -42,10 +170,38 @@ impl Bmp {
         }
     }
 
+    /// Returns the pixel at `(x, y)`, or `None` outside of the image.
+    pub fn get_pixel(&self, x: usize, y: usize) -> Option<BMPixel> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        Some(self.pixels[y * self.width + x])
+    }
+
+    /// Returns the pixel at `(x, y)` without checking `x` against the width.
+    /// Panics if the index falls outside of the pixel buffer.
+    pub fn get_pixel_unchecked(&self, x: usize, y: usize) -> BMPixel {
+        self.pixels[y * self.width + x]
+    }
+
     pub fn set_pixel(&mut self, x: usize, y: usize, pixel: BMPixel) {
         self.pixels[y * self.width + x] = pixel;
     }
 
+    /// Sets the pixel at `(x, y)`, failing instead of panicking outside of the image.
+    pub fn try_set_pixel(&mut self, x: usize, y: usize, pixel: BMPixel) -> Result<(), OutOfBounds> {
+        if x >= self.width || y >= self.height {
+            return Err(OutOfBounds {
+                x,
+                y,
+                width: self.width,
+                height: self.height,
+            });
+        }
+        self.pixels[y * self.width + x] = pixel;
+        Ok(())
+    }
+
     pub fn fill(&mut self, bounds: BoundingBox, pixel: BMPixel) {
         for y in bounds.y1..bounds.y2 {
             for x in bounds.x1..bounds.x2 {
@@ -53,136 +209,4598 @@ impl Bmp {
             }
         }
     }
-}
 
-#[derive(Debug, Clone, Copy)]
-pub struct BoundingBox {
-    pub x1: usize,
-    pub y1: usize,
-    pub x2: usize,
-    pub y2: usize,
-}
+    /// Multiplies every pixel by `color`, so white is a no-op and black zeroes the image.
+    pub fn tint(&mut self, color: BMPixel) {
+        let scale = |channel: u8, factor: u8| -> u32 {
+            ((channel as u32 * factor as u32 + 127) / 255).min(255)
+        };
+        for pixel in self.pixels.iter_mut() {
+            let r = scale(pixel.red(), color.red());
+            let g = scale(pixel.green(), color.green());
+            let b = scale(pixel.blue(), color.blue());
+            pixel.0 = (pixel.0 & 0xff00_0000) | (r << 16) | (g << 8) | b;
+        }
+    }
 
-impl Bmp {
-    /// Reads the ode5 bitmap file.
-    pub fn read_to_bmp(file_path: &Path) -> Self {
-        // Open the file
-        let mut file = File::open(file_path).expect("Unable to open file");
+    fn check_same_dimensions(&self, other: &Bmp) -> Result<(), BmpError> {
+        if self.width != other.width || self.height != other.height {
+            return Err(BmpError::DimensionMismatch {
+                expected: (self.width, self.height),
+                found: (other.width, other.height),
+            });
+        }
+        Ok(())
+    }
 
-        // Read the FileHeader
-        let mut file_header_bytes = [0u8; std::mem::size_of::<FileHeader>()];
-        file.read_exact(&mut file_header_bytes)
-            .expect("Failed to read file header");
-        let file_header = FileHeader::from_bytes(&file_header_bytes);
+    /// Returns the bounding boxes of 8-connected regions where any channel differs by more than `threshold`.
+    pub fn diff_regions(&self, other: &Bmp, threshold: u8) -> Result<Vec<BoundingBox>, BmpError> {
+        self.check_same_dimensions(other)?;
 
-        // Check the file type
-        if file_header._bfType != [0x42, 0x4D] {
-            panic!("Not a BMP file");
+        let differs = |a: BMPixel, b: BMPixel| {
+            a.red().abs_diff(b.red()) > threshold
+                || a.green().abs_diff(b.green()) > threshold
+                || a.blue().abs_diff(b.blue()) > threshold
+        };
+        let mut mask: Vec<bool> = self
+            .pixels
+            .iter()
+            .zip(other.pixels.iter())
+            .map(|(&a, &b)| differs(a, b))
+            .collect();
+
+        let mut regions = Vec::new();
+        let mut stack = Vec::new();
+        for start in 0..mask.len() {
+            if !mask[start] {
+                continue;
+            }
+            mask[start] = false;
+            stack.push(start);
+            let mut bounds = BoundingBox {
+                x1: usize::MAX,
+                y1: usize::MAX,
+                x2: 0,
+                y2: 0,
+            };
+            while let Some(index) = stack.pop() {
+                let (x, y) = (index % self.width, index / self.width);
+                bounds.x1 = bounds.x1.min(x);
+                bounds.y1 = bounds.y1.min(y);
+                bounds.x2 = bounds.x2.max(x + 1);
+                bounds.y2 = bounds.y2.max(y + 1);
+                for ny in y.saturating_sub(1)..(y + 2).min(self.height) {
+                    for nx in x.saturating_sub(1)..(x + 2).min(self.width) {
+                        let neighbour = ny * self.width + nx;
+                        if mask[neighbour] {
+                            mask[neighbour] = false;
+                            stack.push(neighbour);
+                        }
+                    }
+                }
+            }
+            regions.push(bounds);
+        }
+        Ok(regions)
+    }
+
+    /// Returns an FNV-1a hash of each row, top to bottom, for cheaply localizing differences.
+    pub fn row_hashes(&self) -> Vec<u64> {
+        if self.width == 0 {
+            return vec![0xcbf2_9ce4_8422_2325; self.height];
         }
+        self.pixels
+            .chunks(self.width)
+            .map(|row| {
+                row.iter()
+                    .flat_map(|pixel| pixel.0.to_le_bytes())
+                    .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+                        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+                    })
+            })
+            .collect()
+    }
 
-        // Read the InfoHeader
-        let mut info_header_bytes = [0u8; std::mem::size_of::<InfoHeader>()];
-        file.read_exact(&mut info_header_bytes)
-            .expect("Failed to read info header");
-        let info_header = InfoHeader::from_bytes(&info_header_bytes);
+    /// Darkens pixels by their distance from the center; `strength` of 1 blacks out the corners.
+    pub fn vignette(&mut self, strength: f32) {
+        let cx = (self.width as f32 - 1.0) / 2.0;
+        let cy = (self.height as f32 - 1.0) / 2.0;
+        let max_distance_sq = cx * cx + cy * cy;
+        if max_distance_sq == 0.0 {
+            return;
+        }
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let dx = x as f32 - cx;
+                let dy = y as f32 - cy;
+                let falloff = (dx * dx + dy * dy) / max_distance_sq;
+                let factor = (1.0 - strength * falloff).clamp(0.0, 1.0);
+                let index = y * self.width + x;
+                self.pixels[index] = self.pixels[index].scaled(factor);
+            }
+        }
+    }
 
-        // Check that we can handle this BMP file
-        if info_header.biBitCount != 24 {
-            panic!("Only 24-bit BMP files are supported");
+    /// Replaces each `block` x `block` tile with its average color; edge tiles may be smaller.
+    pub fn pixelate(&mut self, block: usize) {
+        if block == 0 {
+            return;
+        }
+        for by in (0..self.height).step_by(block) {
+            for bx in (0..self.width).step_by(block) {
+                let (x2, y2) = ((bx + block).min(self.width), (by + block).min(self.height));
+                // Sums of more than 2^24 pixels can overflow a u32, so accumulate in u64
+                let (mut r, mut g, mut b) = (0u64, 0u64, 0u64);
+                for y in by..y2 {
+                    for x in bx..x2 {
+                        let pixel = self.pixels[y * self.width + x];
+                        r += pixel.red() as u64;
+                        g += pixel.green() as u64;
+                        b += pixel.blue() as u64;
+                    }
+                }
+                let count = ((x2 - bx) * (y2 - by)) as u64;
+                let mean = |sum: u64| ((sum + count / 2) / count) as u32;
+                let average = mean(r) << 16 | mean(g) << 8 | mean(b);
+                for y in by..y2 {
+                    for x in bx..x2 {
+                        let pixel = &mut self.pixels[y * self.width + x];
+                        pixel.0 = (pixel.0 & 0xff00_0000) | average;
+                    }
+                }
+            }
         }
+    }
 
-        if info_header.biCompression != 0 {
-            panic!("Compressed BMP files are not supported");
+    /// Convolves with `h_kernel` along rows and then `v_kernel` along columns, clamping at the edges.
+    ///
+    /// Kernels are centered on their middle element, so odd lengths are expected.
+    pub fn convolve_separable(&self, h_kernel: &[f32], v_kernel: &[f32]) -> Bmp {
+        let mut result = self.clone();
+        if self.width == 0 || self.height == 0 {
+            return result;
         }
 
-        // Read the pixel data
-        let width = info_header.biWidth as usize;
-        let height = info_header.biHeight as usize;
+        // Horizontal pass into an unrounded intermediate buffer
+        let mut intermediate = vec![[0f32; 3]; self.width * self.height];
+        for (y, row) in intermediate.chunks_mut(self.width).enumerate() {
+            self.convolve_row_horizontal(y, h_kernel, row);
+        }
 
-        // Move the file cursor to bfOffBits
-        file.seek(SeekFrom::Start(file_header.bfOffBits as u64))
-            .expect("Failed to seek to pixel data");
+        // Vertical pass
+        for (y, row) in result.pixels.chunks_mut(self.width).enumerate() {
+            self.convolve_row_vertical(&intermediate, y, v_kernel, row);
+        }
+        result
+    }
+
+    /// [`Bmp::convolve_separable`] with the rows of each pass spread across threads.
+    #[cfg(feature = "rayon")]
+    pub fn convolve_separable_parallel(&self, h_kernel: &[f32], v_kernel: &[f32]) -> Bmp {
+        use rayon::prelude::*;
+
+        let mut result = self.clone();
+        if self.width == 0 || self.height == 0 {
+            return result;
+        }
+
+        let mut intermediate = vec![[0f32; 3]; self.width * self.height];
+        intermediate
+            .par_chunks_mut(self.width)
+            .enumerate()
+            .for_each(|(y, row)| self.convolve_row_horizontal(y, h_kernel, row));
+
+        result
+            .pixels
+            .par_chunks_mut(self.width)
+            .enumerate()
+            .for_each(|(y, row)| self.convolve_row_vertical(&intermediate, y, v_kernel, row));
+        result
+    }
+
+    fn convolve_row_horizontal(&self, y: usize, kernel: &[f32], out: &mut [[f32; 3]]) {
+        let radius = kernel.len() as isize / 2;
+        for (x, sum) in out.iter_mut().enumerate() {
+            *sum = [0f32; 3];
+            for (k, weight) in kernel.iter().enumerate() {
+                let sx = (x as isize + k as isize - radius).clamp(0, self.width as isize - 1);
+                let pixel = self.pixels[y * self.width + sx as usize];
+                sum[0] += pixel.red() as f32 * weight;
+                sum[1] += pixel.green() as f32 * weight;
+                sum[2] += pixel.blue() as f32 * weight;
+            }
+        }
+    }
+
+    fn convolve_row_vertical(
+        &self,
+        intermediate: &[[f32; 3]],
+        y: usize,
+        kernel: &[f32],
+        out: &mut [BMPixel],
+    ) {
+        let radius = kernel.len() as isize / 2;
+        let channel = |value: f32| value.round().clamp(0.0, 255.0) as u32;
+        for (x, pixel) in out.iter_mut().enumerate() {
+            let mut sum = [0f32; 3];
+            for (k, weight) in kernel.iter().enumerate() {
+                let sy = (y as isize + k as isize - radius).clamp(0, self.height as isize - 1);
+                let value = intermediate[sy as usize * self.width + x];
+                sum[0] += value[0] * weight;
+                sum[1] += value[1] * weight;
+                sum[2] += value[2] * weight;
+            }
+            pixel.0 = (pixel.0 & 0xff00_0000)
+                | (channel(sum[0]) << 16)
+                | (channel(sum[1]) << 8)
+                | channel(sum[2]);
+        }
+    }
+
+    /// Returns the red, green, and blue histograms of the pixels inside `bounds`, clamped to the image.
+    pub fn histogram_in_box(&self, bounds: BoundingBox) -> ([u32; 256], [u32; 256], [u32; 256]) {
+        let bounds = bounds.clamped(self.width, self.height);
+        let (mut red, mut green, mut blue) = ([0u32; 256], [0u32; 256], [0u32; 256]);
+        for y in bounds.y1..bounds.y2 {
+            for pixel in &self.pixels[y * self.width + bounds.x1..y * self.width + bounds.x2] {
+                red[pixel.red() as usize] += 1;
+                green[pixel.green() as usize] += 1;
+                blue[pixel.blue() as usize] += 1;
+            }
+        }
+        (red, green, blue)
+    }
+
+    /// Sets the alpha (top) byte of every pixel to `alpha`, leaving the color untouched.
+    pub fn fill_alpha(&mut self, alpha: u8) {
+        for pixel in self.pixels.iter_mut() {
+            *pixel = pixel.with_alpha(alpha);
+        }
+    }
+
+    /// Returns true if both images have the same dimensions and no channel differs by more than `tolerance`.
+    pub fn equal_within(&self, other: &Bmp, tolerance: u8) -> bool {
+        self.width == other.width
+            && self.height == other.height
+            && self.pixels.iter().zip(other.pixels.iter()).all(|(a, b)| {
+                a.0.to_le_bytes()
+                    .iter()
+                    .zip(b.0.to_le_bytes().iter())
+                    .all(|(x, y)| x.abs_diff(*y) <= tolerance)
+            })
+    }
+
+    /// Builds an image from one luminance byte per pixel, in row-major order with the top row
+    /// first.
+    pub fn from_grayscale_bytes(
+        width: usize,
+        height: usize,
+        bytes: &[u8],
+    ) -> Result<Bmp, BmpError> {
+        if bytes.len() != width * height {
+            return Err(BmpError::InvalidLength {
+                expected: width * height,
+                found: bytes.len(),
+            });
+        }
+        let pixels = bytes
+            .iter()
+            .map(|&gray| BMPixel(gray as u32 * 0x01_0101))
+            .collect();
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
 
-        let bytes_per_row = (width as f64 / (8.0 / 24_f64)).ceil() as usize;
-        let mut data = vec![0u8; info_header.biSizeImage as usize];
-        file.read_exact(&mut data)
-            .expect("Failed to read pixel data");
+    /// Maps each pixel's luminance through `colormap`, e.g. [`crate::colormap::JET`] for false color.
+    pub fn apply_colormap(&mut self, colormap: &[BMPixel; 256]) {
+        for pixel in self.pixels.iter_mut() {
+            let mapped = colormap[pixel.luminance() as usize];
+            pixel.0 = (pixel.0 & 0xff00_0000) | (mapped.0 & 0x00ff_ffff);
+        }
+    }
+
+    /// Replaces every pixel inside the clamped `bounds` with `f(pixel)`.
+    fn map_region(&mut self, bounds: BoundingBox, mut f: impl FnMut(BMPixel) -> BMPixel) {
+        let bounds = bounds.clamped(self.width, self.height);
+        for y in bounds.y1..bounds.y2 {
+            for pixel in &mut self.pixels[y * self.width + bounds.x1..y * self.width + bounds.x2] {
+                *pixel = f(*pixel);
+            }
+        }
+    }
+
+    /// Rotates the hue of the pixels inside `bounds` by `degrees`, leaving the rest untouched.
+    pub fn rotate_hue_region(&mut self, bounds: BoundingBox, degrees: f32) {
+        self.map_region(bounds, |pixel| {
+            let (hue, saturation, value) = pixel.to_hsv();
+            pixel.with_hsv(hue + degrees, saturation, value)
+        });
+    }
+
+    /// Yields each row as it is stored in the file: BGR triplets plus padding, in bottom-up file
+    /// order.
+    pub fn packed_rows(&self) -> impl Iterator<Item = Vec<u8>> + '_ {
+        (0..self.height)
+            .rev()
+            .map(|y| encode_row(&self.pixels[y * self.width..(y + 1) * self.width]))
+    }
+
+    /// Borrows the region inside `bounds`, clamped to the image, without copying it.
+    pub fn view(&self, bounds: BoundingBox) -> SubImage<'_> {
+        SubImage {
+            bmp: self,
+            bounds: bounds.clamped(self.width, self.height),
+        }
+    }
+
+    /// Blends `other` onto this image with its top-left corner at `(x, y)`, clipping what falls outside.
+    pub fn composite(&mut self, other: &Bmp, x: usize, y: usize, mode: BlendMode) {
+        let x2 = x.saturating_add(other.width).min(self.width);
+        let y2 = y.saturating_add(other.height).min(self.height);
+        for dy in y..y2 {
+            for dx in x..x2 {
+                let layer = other.pixels[(dy - y) * other.width + (dx - x)];
+                let base = &mut self.pixels[dy * self.width + dx];
+                *base = mode.blend(*base, layer);
+            }
+        }
+    }
 
+    /// Builds a `width` x `height` image whose pixel `(x, y)` is taken from `self` at `source(x, y)`.
+    fn remapped(
+        &self,
+        width: usize,
+        height: usize,
+        source: impl Fn(usize, usize) -> (usize, usize),
+    ) -> Bmp {
         let mut pixels = Vec::with_capacity(width * height);
         for y in 0..height {
             for x in 0..width {
-                let data_index = y * bytes_per_row + x * 3;
-                let b = data[data_index];
-                let g = data[data_index + 1];
-                let r = data[data_index + 2];
-                let pixel_value = ((r as u32) << 16) | ((g as u32) << 8) | (b as u32);
-                pixels.push(BMPixel(pixel_value));
+                let (sx, sy) = source(x, y);
+                pixels.push(self.pixels[sy * self.width + sx]);
             }
         }
-
-        Self {
+        Bmp {
             width,
             height,
             pixels,
         }
     }
 
-    pub fn write_to_file(&self, file_path: &Path) -> Result<(), std::io::Error> {
-        let ode5bmp = Ode5Bmp::new(self);
-        let mut file = File::create(file_path).expect("Unable to create file");
-
-        file.write_all(&ode5bmp.to_bytes())
+    /// Applies the transform for an EXIF `orientation` obtained from outside the BMP.
+    pub fn auto_orient(&mut self, orientation: Orientation) {
+        match orientation {
+            Orientation::Normal => {}
+            Orientation::FlipHorizontal => self.flip_horizontal(),
+            Orientation::Rotate180 => self.rotate_180_in_place(),
+            Orientation::FlipVertical => self.flip_vertical(),
+            // The transposes are quarter turns mirrored left to right
+            Orientation::Transpose => {
+                *self = self.rotate90();
+                self.flip_horizontal();
+            }
+            Orientation::Rotate90 => *self = self.rotate90(),
+            Orientation::Transverse => {
+                *self = self.rotate270();
+                self.flip_horizontal();
+            }
+            Orientation::Rotate270 => *self = self.rotate270(),
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use rstest::rstest;
+    /// Computes a 64-bit difference hash: each bit says whether a cell of a 9x8 luminance
+    /// thumbnail is darker than its right neighbour. Similar images give nearby hashes.
+    pub fn dhash(&self) -> u64 {
+        if self.width == 0 || self.height == 0 {
+            return 0;
+        }
+        let cell_range = |cell: usize, cells: usize, len: usize| {
+            let start = cell * len / cells;
+            let end = ((cell + 1) * len / cells).max(start + 1).min(len);
+            start.min(len - 1)..end
+        };
+        let mut thumbnail = [[0u32; 9]; 8];
+        for (cy, row) in thumbnail.iter_mut().enumerate() {
+            let ys = cell_range(cy, 8, self.height);
+            for (cx, cell) in row.iter_mut().enumerate() {
+                let xs = cell_range(cx, 9, self.width);
+                let mut sum = 0;
+                for y in ys.clone() {
+                    for x in xs.clone() {
+                        sum += self.pixels[y * self.width + x].luminance() as u32;
+                    }
+                }
+                *cell = sum / (ys.len() * xs.len()) as u32;
+            }
+        }
 
-    #[rstest]
-    #[case("data/greenblue_square-1794933754679872826.bmp", 12, 12)]
-    #[case("data/france-7921693104947760092.bmp", 30, 20)]
-    #[case("data/handcrafted-2044735835957623026.bmp", 5, 5)]
-    fn test_read_bmp(#[case] file_path: &str, #[case] width: usize, #[case] height: usize) {
-        let bmp = Bmp::read_to_bmp(Path::new(file_path));
-        assert_eq!(bmp.width, width);
-        assert_eq!(bmp.height, height);
+        let mut hash = 0u64;
+        for row in thumbnail.iter() {
+            for pair in row.windows(2) {
+                hash = (hash << 1) | (pair[0] < pair[1]) as u64;
+            }
+        }
+        hash
     }
 
-    #[test]
-    fn test_read_bmp_pixels() {
-        let bmp = Bmp::read_to_bmp(Path::new("data/test.bmp"));
-        assert_eq!(bmp.width, 45);
-        assert_eq!(bmp.height, 30);
-        assert_eq!(bmp.pixels[0], BMPixel(0x00_00ff));
+    /// Shrinks the image by averaging every source pixel a destination pixel covers, weighted by
+    /// the covered area, which avoids aliasing for large or fractional reductions.
+    pub fn supersample_downscale(&self, new_width: usize, new_height: usize) -> Bmp {
+        let mut result = Bmp::new(new_width, new_height);
+        if self.width == 0 || self.height == 0 {
+            return result;
+        }
+
+        // For each destination column (or row), the source indices it overlaps and by how much
+        let coverage = |src_len: usize, dst_len: usize| -> Vec<Vec<(usize, f64)>> {
+            let scale = src_len as f64 / dst_len as f64;
+            (0..dst_len)
+                .map(|d| {
+                    let (start, end) = (d as f64 * scale, (d + 1) as f64 * scale);
+                    (start.floor() as usize..(end.ceil() as usize).min(src_len))
+                        .map(|s| (s, end.min(s as f64 + 1.0) - start.max(s as f64)))
+                        .filter(|&(_, weight)| weight > 0.0)
+                        .collect()
+                })
+                .collect()
+        };
+        let columns = coverage(self.width, new_width);
+        let rows = coverage(self.height, new_height);
+
+        for (dy, row) in rows.iter().enumerate() {
+            for (dx, column) in columns.iter().enumerate() {
+                let mut sum = [0f64; 4];
+                let mut total = 0f64;
+                for &(sy, wy) in row {
+                    for &(sx, wx) in column {
+                        let bytes = self.pixels[sy * self.width + sx].0.to_le_bytes();
+                        for (acc, byte) in sum.iter_mut().zip(bytes) {
+                            *acc += byte as f64 * wx * wy;
+                        }
+                        total += wx * wy;
+                    }
+                }
+                let bytes = sum.map(|acc| (acc / total).round().clamp(0.0, 255.0) as u8);
+                result.pixels[dy * new_width + dx] = BMPixel(u32::from_le_bytes(bytes));
+            }
+        }
+        result
     }
 
-    #[test]
-    fn test_write_bmp() {
-        let mut bmp = Bmp::new(45, 30);
-        bmp.fill(
-            BoundingBox {
-                x1: 0,
+    /// Draws 1px grid lines at every multiple of `cell_width` and `cell_height`, starting at 0.
+    pub fn draw_grid(&mut self, cell_width: usize, cell_height: usize, color: BMPixel) {
+        if cell_width == 0 || cell_height == 0 {
+            return;
+        }
+        for x in (0..self.width).step_by(cell_width) {
+            let line = BoundingBox {
+                x1: x,
                 y1: 0,
-                x2: 45,
-                y2: 30,
-            },
-            BMPixel(0x00_00ff),
+                x2: x + 1,
+                y2: self.height,
+            };
+            self.fill(line, color);
+        }
+        for y in (0..self.height).step_by(cell_height) {
+            let line = BoundingBox {
+                x1: 0,
+                y1: y,
+                x2: self.width,
+                y2: y + 1,
+            };
+            self.fill(line, color);
+        }
+    }
+
+    /// Returns the most frequent pixel value, preferring the smallest value on ties.
+    pub fn most_common_color(&self) -> Option<BMPixel> {
+        let mut counts: HashMap<BMPixel, usize> = HashMap::new();
+        for &pixel in &self.pixels {
+            *counts.entry(pixel).or_default() += 1;
+        }
+        counts
+            .into_iter()
+            .max_by_key(|&(pixel, count)| (count, Reverse(pixel)))
+            .map(|(pixel, _)| pixel)
+    }
+
+    /// Copies `src` into `bounds`, which must lie inside the image and match `src` exactly in size.
+    pub fn replace_region(&mut self, bounds: BoundingBox, src: &Bmp) -> Result<(), BmpError> {
+        if bounds.x1 > bounds.x2
+            || bounds.y1 > bounds.y2
+            || bounds.x2 > self.width
+            || bounds.y2 > self.height
+        {
+            return Err(BmpError::InvalidBounds);
+        }
+        let size = (bounds.x2 - bounds.x1, bounds.y2 - bounds.y1);
+        if size != (src.width, src.height) {
+            return Err(BmpError::DimensionMismatch {
+                expected: size,
+                found: (src.width, src.height),
+            });
+        }
+        for (row, y) in (bounds.y1..bounds.y2).enumerate() {
+            let start = y * self.width + bounds.x1;
+            self.pixels[start..start + src.width]
+                .copy_from_slice(&src.pixels[row * src.width..(row + 1) * src.width]);
+        }
+        Ok(())
+    }
+
+    /// Encodes the image as a BMP file into `buf`, reusing its capacity across calls.
+    pub fn encode_into(&self, buf: &mut Vec<u8>) -> Result<(), BmpError> {
+        Ode5Bmp::encode_into(self, buf);
+        Ok(())
+    }
+
+    /// Builds an image from rows of characters, each mapped to one pixel through `palette`.
+    pub fn from_ascii(rows: &[&str], palette: &HashMap<char, BMPixel>) -> Result<Bmp, BmpError> {
+        let width = rows.first().map_or(0, |row| row.chars().count());
+        let mut pixels = Vec::with_capacity(width * rows.len());
+        for row in rows {
+            let length = row.chars().count();
+            if length != width {
+                return Err(BmpError::InvalidLength {
+                    expected: width,
+                    found: length,
+                });
+            }
+            for symbol in row.chars() {
+                let pixel = palette
+                    .get(&symbol)
+                    .ok_or(BmpError::UnknownSymbol(symbol))?;
+                pixels.push(*pixel);
+            }
+        }
+        Ok(Self {
+            width,
+            height: rows.len(),
+            pixels,
+        })
+    }
+
+    /// Renders each pixel as its `palette` character, or `unknown`; the inverse of [`Bmp::from_ascii`].
+    pub fn to_ascii(&self, palette: &HashMap<BMPixel, char>, unknown: char) -> Vec<String> {
+        (0..self.height)
+            .map(|y| {
+                self.pixels[y * self.width..(y + 1) * self.width]
+                    .iter()
+                    .map(|pixel| palette.get(pixel).copied().unwrap_or(unknown))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Translates each channel by its own `(dx, dy)` offset for a chromatic-aberration look.
+    /// Samples from outside the image take the matching channel of `fill`.
+    pub fn shift_channels(
+        &mut self,
+        r_offset: (isize, isize),
+        g_offset: (isize, isize),
+        b_offset: (isize, isize),
+        fill: BMPixel,
+    ) {
+        let source = self.clone();
+        let sample = |x: usize, y: usize, (dx, dy): (isize, isize), shift: u32| -> u32 {
+            let sx = x as isize - dx;
+            let sy = y as isize - dy;
+            let pixel =
+                if sx < 0 || sy < 0 || sx >= self.width as isize || sy >= self.height as isize {
+                    fill
+                } else {
+                    source.pixels[sy as usize * source.width + sx as usize]
+                };
+            pixel.0 & (0xff << shift)
+        };
+        let mut pixels = Vec::with_capacity(self.pixels.len());
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let top = source.pixels[y * self.width + x].0 & 0xff00_0000;
+                pixels.push(BMPixel(
+                    top | sample(x, y, r_offset, 16)
+                        | sample(x, y, g_offset, 8)
+                        | sample(x, y, b_offset, 0),
+                ));
+            }
+        }
+        self.pixels = pixels;
+    }
+
+    /// Returns true when every pixel has an alpha (top byte) of 255, or when the image has no
+    /// alpha at all, like a freshly read 24-bit file.
+    pub fn is_opaque(&self) -> bool {
+        !self.has_alpha() || self.pixels.iter().all(|pixel| pixel.alpha() == 0xff)
+    }
+
+    /// A top byte that is 0 everywhere is treated as "no alpha" rather than fully transparent.
+    fn has_alpha(&self) -> bool {
+        self.pixels.iter().any(|pixel| pixel.alpha() != 0)
+    }
+
+    /// Writes the smallest lossless encoding: 32-bit BGRA with a V5 header if alpha is in use,
+    /// otherwise 1-bit for two colors, 8-bit indexed for up to 256 (which covers any grayscale
+    /// image), else 24-bit.
+    ///
+    /// A top byte that is 0 everywhere is treated as "no alpha", like a freshly read 24-bit file.
+    pub fn write_auto(&self, file_path: &Path) -> Result<(), BmpError> {
+        let bytes = if !self.is_opaque() {
+            Ode5Bmp::new_bgra(self).to_bytes_v5()
+        } else {
+            let palette = self.distinct_colors(256);
+            match palette.len() {
+                0..=2 => Ode5Bmp::new_indexed(self, &palette, 1).to_bytes(),
+                3..=256 => Ode5Bmp::new_indexed(self, &palette, 8).to_bytes(),
+                _ => Ode5Bmp::new(self).to_bytes(),
+            }
+        };
+        let mut file = File::create(file_path)?;
+        file.write_all(&bytes)?;
+        Ok(())
+    }
+
+    /// Collects the sorted distinct RGB values, ignoring alpha, stopping once there are more
+    /// than `limit`.
+    fn distinct_colors(&self, limit: usize) -> Vec<BMPixel> {
+        let mut colors = HashSet::new();
+        for pixel in &self.pixels {
+            if colors.insert(BMPixel(pixel.0 & 0x00ff_ffff)) && colors.len() > limit {
+                break;
+            }
+        }
+        let mut palette: Vec<BMPixel> = colors.into_iter().collect();
+        palette.sort();
+        palette
+    }
+
+    /// Writes an 8-bit paletted BMP, failing with [`BmpError::TooManyColors`] when the image has
+    /// more than 256 distinct colors and needs [`Bmp::write_to_file`] instead. Alpha is dropped.
+    pub fn write_to_file_indexed(&self, path: &Path) -> Result<(), BmpError> {
+        let palette = self.distinct_colors(256);
+        if palette.len() > 256 {
+            return Err(BmpError::TooManyColors { limit: 256 });
+        }
+        let mut file = File::create(path)?;
+        file.write_all(&Ode5Bmp::new_indexed(self, &palette, 8).to_bytes())?;
+        Ok(())
+    }
+
+    /// Rotates by 180 degrees without allocating: with row-major storage, reversing the
+    /// pixels reverses both axes at once.
+    pub fn rotate_180_in_place(&mut self) {
+        self.pixels.reverse();
+    }
+
+    /// Returns a copy surrounded by margins of the given sizes, filled with `fill`.
+    pub fn add_margin(
+        &self,
+        left: usize,
+        right: usize,
+        top: usize,
+        bottom: usize,
+        fill: BMPixel,
+    ) -> Bmp {
+        let width = left + self.width + right;
+        let height = top + self.height + bottom;
+        let mut result = Bmp {
+            width,
+            height,
+            pixels: vec![fill; width * height],
+        };
+        for y in 0..self.height {
+            let start = (top + y) * width + left;
+            result.pixels[start..start + self.width]
+                .copy_from_slice(&self.pixels[y * self.width..(y + 1) * self.width]);
+        }
+        result
+    }
+
+    /// Computes min, max, mean, and standard deviation of each channel in a single pass.
+    pub fn channel_statistics(&self) -> ChannelStats {
+        let (mut red, mut green, mut blue) = (
+            RunningStats::default(),
+            RunningStats::default(),
+            RunningStats::default(),
         );
-        bmp.write_to_file(Path::new("data/test.bmp")).unwrap();
+        for pixel in &self.pixels {
+            red.push(pixel.red());
+            green.push(pixel.green());
+            blue.push(pixel.blue());
+        }
+        ChannelStats {
+            red: red.summary(),
+            green: green.summary(),
+            blue: blue.summary(),
+        }
     }
 
-    #[test]
-    fn test_roundtrip() {
-        let bmp = Bmp::read_to_bmp(Path::new("data/greenblue_square-1794933754679872826.bmp"));
-        bmp.write_to_file(Path::new(
-            "data/tmp-greenblue_square-1794933754679872826.bmp",
-        ))
-        .unwrap();
-        let bmp2 = Bmp::read_to_bmp(Path::new(
-            "data/tmp-greenblue_square-1794933754679872826.bmp",
-        ));
-        assert_eq!(bmp, bmp2);
+    /// Snaps each channel to the nearest web-safe level (multiples of 0x33).
+    pub fn to_web_safe(&mut self) {
+        let snap = |channel: u8| (channel as u32 + 25) / 51 * 51;
+        for pixel in self.pixels.iter_mut() {
+            pixel.0 = (pixel.0 & 0xff00_0000)
+                | snap(pixel.red()) << 16
+                | snap(pixel.green()) << 8
+                | snap(pixel.blue());
+        }
+    }
+
+    /// Returns the `(x, y)` of every pixel exactly equal to `color`, in row-major order.
+    pub fn find_color(&self, color: BMPixel) -> Vec<(usize, usize)> {
+        self.pixels
+            .iter()
+            .enumerate()
+            .filter(|&(_, &pixel)| pixel == color)
+            .map(|(index, _)| (index % self.width, index / self.width))
+            .collect()
+    }
+
+    /// Replaces each pixel's color with its nearest `palette` entry, without dithering.
+    pub fn clamp_to_palette(&mut self, palette: &[BMPixel]) {
+        for pixel in self.pixels.iter_mut() {
+            if let Some(nearest) = pixel.nearest_color(palette) {
+                pixel.0 = (pixel.0 & 0xff00_0000) | (nearest.0 & 0x00ff_ffff);
+            }
+        }
+    }
+
+    /// Rotates a square image clockwise by `quarter_turns` without allocating.
+    pub fn try_rotate_in_place(&mut self, quarter_turns: u8) -> Result<(), BmpError> {
+        if self.width != self.height {
+            return Err(BmpError::NotSquare {
+                width: self.width,
+                height: self.height,
+            });
+        }
+        let n = self.width;
+        let index = |x: usize, y: usize| y * n + x;
+        match quarter_turns % 4 {
+            0 => {}
+            2 => self.rotate_180_in_place(),
+            turns => {
+                // Cycle the four pixels that trade places, one ring at a time
+                for i in 0..n / 2 {
+                    for j in i..n - 1 - i {
+                        let top = index(j, i);
+                        let right = index(n - 1 - i, j);
+                        let bottom = index(n - 1 - j, n - 1 - i);
+                        let left = index(i, n - 1 - j);
+                        if turns == 1 {
+                            let tmp = self.pixels[left];
+                            self.pixels[left] = self.pixels[bottom];
+                            self.pixels[bottom] = self.pixels[right];
+                            self.pixels[right] = self.pixels[top];
+                            self.pixels[top] = tmp;
+                        } else {
+                            let tmp = self.pixels[top];
+                            self.pixels[top] = self.pixels[right];
+                            self.pixels[right] = self.pixels[bottom];
+                            self.pixels[bottom] = self.pixels[left];
+                            self.pixels[left] = tmp;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Composites the image over a solid `background` using each pixel's alpha, returning an
+    /// opaque result ready for 24-bit output. An image without alpha is returned as is, opaque.
+    pub fn flatten(&self, background: BMPixel) -> Bmp {
+        let mut result = self.clone();
+        if !self.has_alpha() {
+            result.fill_alpha(0xff);
+            return result;
+        }
+        for pixel in result.pixels.iter_mut() {
+            let alpha = pixel.alpha() as u32;
+            let over = |fg: u8, bg: u8| (fg as u32 * alpha + bg as u32 * (255 - alpha) + 127) / 255;
+            *pixel = BMPixel(
+                0xff00_0000
+                    | over(pixel.red(), background.red()) << 16
+                    | over(pixel.green(), background.green()) << 8
+                    | over(pixel.blue(), background.blue()),
+            );
+        }
+        result
+    }
+
+    /// Fills the clamped `bounds` with pseudo-random colors; the same `seed` gives the same noise.
+    pub fn fill_noise(&mut self, bounds: BoundingBox, seed: u64) {
+        let mut rng = SplitMix64(seed);
+        self.map_region(bounds, |pixel| {
+            BMPixel((pixel.0 & 0xff00_0000) | (rng.next_u64() as u32 & 0x00ff_ffff))
+        });
+    }
+
+    /// Visits every point of the Bresenham line from `(x0, y0)` to `(x1, y1)`, in order.
+    fn bresenham(x0: usize, y0: usize, x1: usize, y1: usize, mut visit: impl FnMut(usize, usize)) {
+        let (mut x, mut y) = (x0 as isize, y0 as isize);
+        let (x1, y1) = (x1 as isize, y1 as isize);
+        let dx = (x1 - x).abs();
+        let dy = -(y1 - y).abs();
+        let sx = if x < x1 { 1 } else { -1 };
+        let sy = if y < y1 { 1 } else { -1 };
+        let mut err = dx + dy;
+        loop {
+            visit(x as usize, y as usize);
+            if x == x1 && y == y1 {
+                break;
+            }
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                err += dx;
+                y += sy;
+            }
+        }
+    }
+
+    /// Strokes a Bresenham line that alternates `dash` drawn pixels with `gap` skipped ones.
+    /// Points outside the image are clipped.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_dashed_line(
+        &mut self,
+        x0: usize,
+        y0: usize,
+        x1: usize,
+        y1: usize,
+        color: BMPixel,
+        dash: usize,
+        gap: usize,
+    ) {
+        if dash == 0 {
+            return;
+        }
+        let mut step = 0;
+        Self::bresenham(x0, y0, x1, y1, |x, y| {
+            if step % (dash + gap) < dash && x < self.width && y < self.height {
+                self.pixels[y * self.width + x] = color;
+            }
+            step += 1;
+        });
+    }
+
+    /// Computes a palette of at most `max_colors` entries by median cut: the box of colors with
+    /// the widest channel range is repeatedly split at its median, and each box is averaged.
+    pub fn median_cut_palette(&self, max_colors: usize) -> Vec<BMPixel> {
+        let channel = |pixel: &BMPixel, c: usize| (pixel.0 >> (16 - 8 * c)) as u8;
+        let widest = |colors: &[BMPixel]| -> (usize, u8) {
+            (0..3)
+                .map(|c| {
+                    let (min, max) = colors.iter().fold((u8::MAX, u8::MIN), |(min, max), p| {
+                        (min.min(channel(p, c)), max.max(channel(p, c)))
+                    });
+                    (c, max.saturating_sub(min))
+                })
+                .max_by_key(|&(_, range)| range)
+                .unwrap()
+        };
+
+        let mut boxes: Vec<Vec<BMPixel>> = Vec::new();
+        if !self.pixels.is_empty() && max_colors > 0 {
+            boxes.push(
+                self.pixels
+                    .iter()
+                    .map(|p| BMPixel(p.0 & 0x00ff_ffff))
+                    .collect(),
+            );
+        }
+        while boxes.len() < max_colors {
+            let Some((index, (c, _))) = boxes
+                .iter()
+                .map(|colors| widest(colors))
+                .enumerate()
+                .filter(|&(_, (_, range))| range > 0)
+                .max_by_key(|&(_, (_, range))| range)
+            else {
+                break;
+            };
+            let mut colors = boxes.swap_remove(index);
+            colors.sort_unstable_by_key(|p| channel(p, c));
+            // Split at the median, nudged so equal values stay on one side. When the pivot is the
+            // smallest value, its run goes below the split; the box spans a range, so both halves
+            // stay nonempty.
+            let pivot = channel(&colors[colors.len() / 2], c);
+            let median = match colors.partition_point(|p| channel(p, c) < pivot) {
+                0 => colors.partition_point(|p| channel(p, c) <= pivot),
+                median => median,
+            };
+            let upper = colors.split_off(median);
+            boxes.push(colors);
+            boxes.push(upper);
+        }
+
+        let mut palette: Vec<BMPixel> = boxes
+            .iter()
+            .map(|colors| {
+                let n = colors.len() as u32;
+                let average =
+                    |c| (colors.iter().map(|p| channel(p, c) as u32).sum::<u32>() + n / 2) / n;
+                BMPixel(average(0) << 16 | average(1) << 8 | average(2))
+            })
+            .collect();
+        palette.sort();
+        palette.dedup();
+        palette
+    }
+
+    /// Run-length encodes row `y` as `(color, count)` pairs, or `None` if `y` is out of range.
+    pub fn row_run_lengths(&self, y: usize) -> Option<Vec<(BMPixel, usize)>> {
+        if y >= self.height {
+            return None;
+        }
+        let mut runs: Vec<(BMPixel, usize)> = Vec::new();
+        for &pixel in &self.pixels[y * self.width..(y + 1) * self.width] {
+            match runs.last_mut() {
+                Some((color, count)) if *color == pixel => *count += 1,
+                _ => runs.push((pixel, 1)),
+            }
+        }
+        Some(runs)
+    }
+
+    /// Renders `text` with the embedded 3x5 font, its top-left corner at `(x, y)`.
+    /// Newlines start a new line; anything outside the image is clipped.
+    pub fn draw_text(&mut self, x: usize, y: usize, text: &str, color: BMPixel) {
+        for (line_index, line) in text.lines().enumerate() {
+            let top = y + line_index * font::LINE_HEIGHT;
+            for (char_index, c) in line.chars().enumerate() {
+                let left = x + char_index * font::ADVANCE;
+                for (row, bits) in font::glyph(c).iter().enumerate() {
+                    for column in 0..font::GLYPH_WIDTH {
+                        let (px, py) = (left + column, top + row);
+                        if bits >> (font::GLYPH_WIDTH - 1 - column) & 1 == 1
+                            && px < self.width
+                            && py < self.height
+                        {
+                            self.pixels[py * self.width + px] = color;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Renders a single line of `text` horizontally centered, with its top at `y`.
+    pub fn draw_text_centered(&mut self, text: &str, y: usize, color: BMPixel) {
+        let x = self.width.saturating_sub(font::line_width(text)) / 2;
+        self.draw_text(x, y, text, color);
+    }
+
+    /// Renders `text` at `(x, y)`, wrapping at spaces so each line fits in `max_width` pixels.
+    pub fn draw_text_wrapped(
+        &mut self,
+        x: usize,
+        y: usize,
+        text: &str,
+        max_width: usize,
+        color: BMPixel,
+    ) {
+        let lines = font::wrap_text(text, max_width);
+        self.draw_text(x, y, &lines.join("\n"), color);
+    }
+
+    /// Computes the summed-area table of luminance, so any box sum takes four lookups.
+    ///
+    /// The table is `(width + 1) x (height + 1)` with a leading row and column of zeros: entry
+    /// `(x, y)` holds the sum of all pixels above and left of it, and the sum over `x1..x2`,
+    /// `y1..y2` is `t(x2, y2) - t(x1, y2) - t(x2, y1) + t(x1, y1)`.
+    pub fn integral_image(&self) -> Vec<u64> {
+        let stride = self.width + 1;
+        let mut table = vec![0u64; stride * (self.height + 1)];
+        for y in 0..self.height {
+            let mut row_sum = 0u64;
+            for x in 0..self.width {
+                row_sum += self.pixels[y * self.width + x].luminance() as u64;
+                table[(y + 1) * stride + x + 1] = table[y * stride + x + 1] + row_sum;
+            }
+        }
+        table
+    }
+
+    /// Binarizes each pixel against the mean luminance of the `window` x `window` neighbourhood
+    /// around it plus `bias`: brighter pixels become white, the rest black. Unlike a global
+    /// threshold this copes with uneven lighting. Windows are clipped at the image edges.
+    pub fn adaptive_threshold(&mut self, window: usize, bias: i16) {
+        let (width, height) = (self.width, self.height);
+        let table = self.integral_image();
+
+        let half = window / 2;
+        for y in 0..height {
+            let (y1, y2) = (y.saturating_sub(half), (y + half + 1).min(height));
+            for x in 0..width {
+                let (x1, x2) = (x.saturating_sub(half), (x + half + 1).min(width));
+                let sum = table[y2 * (width + 1) + x2] + table[y1 * (width + 1) + x1]
+                    - table[y1 * (width + 1) + x2]
+                    - table[y2 * (width + 1) + x1];
+                let mean = sum as f32 / ((x2 - x1) * (y2 - y1)) as f32;
+                let pixel = &mut self.pixels[y * width + x];
+                let value = if pixel.luminance() as f32 > mean + bias as f32 {
+                    0x00ff_ffff
+                } else {
+                    0
+                };
+                pixel.0 = (pixel.0 & 0xff00_0000) | value;
+            }
+        }
+    }
+
+    /// Lists the pixels as `#RRGGBB`, one line per row, for readable test failure output.
+    pub fn debug_dump(&self) -> String {
+        (0..self.height)
+            .map(|y| {
+                self.pixels[y * self.width..(y + 1) * self.width]
+                    .iter()
+                    .map(|pixel| pixel.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Returns a copy with the canvas grown so both dimensions are powers of two, keeping the
+    /// image at the top-left and filling the new area with `fill`.
+    pub fn pad_to_power_of_two(&self, fill: BMPixel) -> Bmp {
+        let width = self.width.next_power_of_two();
+        let height = self.height.next_power_of_two();
+        self.add_margin(0, width - self.width, 0, height - self.height, fill)
+    }
+
+    /// Renders the luminance histogram as white bars on black, scaled so the tallest bin fills
+    /// `height`. Each of the 256 bins spans `width / 256` columns, rounded.
+    pub fn render_histogram(&self, width: usize, height: usize) -> Bmp {
+        let mut bins = [0usize; 256];
+        for pixel in &self.pixels {
+            bins[pixel.luminance() as usize] += 1;
+        }
+        let tallest = bins.iter().copied().max().unwrap_or(0).max(1);
+
+        let mut plot = Bmp::new(width, height);
+        for (bin, &count) in bins.iter().enumerate() {
+            let bar_height = (count * height).div_ceil(tallest);
+            let bar = BoundingBox {
+                x1: bin * width / 256,
+                y1: height - bar_height,
+                x2: (bin + 1) * width / 256,
+                y2: height,
+            };
+            plot.fill(bar, BMPixel(0xff_ffff));
+        }
+        plot
+    }
+
+    /// Center-crops to the largest region with a `ratio_w:ratio_h` aspect ratio.
+    pub fn crop_to_aspect(&self, ratio_w: usize, ratio_h: usize) -> Bmp {
+        if ratio_w == 0 || ratio_h == 0 {
+            return self.clone();
+        }
+        let (width, height) = if self.width * ratio_h > self.height * ratio_w {
+            (self.height * ratio_w / ratio_h, self.height)
+        } else {
+            (self.width, self.width * ratio_h / ratio_w)
+        };
+        let left = (self.width - width) / 2;
+        let top = (self.height - height) / 2;
+        self.remapped(width, height, |x, y| (left + x, top + y))
+    }
+
+    /// Sharpens by adding back `amount` times the difference from a Gaussian blur of `sigma`.
+    pub fn unsharp_mask(&mut self, sigma: f32, amount: f32) {
+        let kernel = gaussian_kernel(sigma);
+        let blurred = self.convolve_separable(&kernel, &kernel);
+        for (pixel, soft) in self.pixels.iter_mut().zip(blurred.pixels.iter()) {
+            let sharpen = |original: u8, blurred: u8| {
+                let original = original as f32;
+                (original + amount * (original - blurred as f32))
+                    .round()
+                    .clamp(0.0, 255.0) as u32
+            };
+            pixel.0 = (pixel.0 & 0xff00_0000)
+                | sharpen(pixel.red(), soft.red()) << 16
+                | sharpen(pixel.green(), soft.green()) << 8
+                | sharpen(pixel.blue(), soft.blue());
+        }
+    }
+
+    /// Swaps the red and blue channels of every pixel, keeping green and alpha.
+    pub fn swap_red_blue(&mut self) {
+        for pixel in &mut self.pixels {
+            let p = pixel.0;
+            pixel.0 = (p & 0xff00_ff00) | ((p >> 16) & 0xff) | ((p & 0xff) << 16);
+        }
+    }
+
+    /// Returns the tightest box around all pixels with nonzero alpha, or `None` for an empty image.
+    /// An alpha of 0 everywhere means no alpha, like a freshly read 24-bit file, so the box then
+    /// covers the whole image.
+    pub fn opaque_bounds(&self) -> Option<BoundingBox> {
+        if !self.has_alpha() {
+            return (!self.pixels.is_empty()).then_some(BoundingBox {
+                x1: 0,
+                y1: 0,
+                x2: self.width,
+                y2: self.height,
+            });
+        }
+        let mut bounds: Option<BoundingBox> = None;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if self.pixels[y * self.width + x].alpha() == 0 {
+                    continue;
+                }
+                let b = bounds.get_or_insert(BoundingBox {
+                    x1: x,
+                    y1: y,
+                    x2: x + 1,
+                    y2: y + 1,
+                });
+                b.x1 = b.x1.min(x);
+                b.x2 = b.x2.max(x + 1);
+                b.y2 = y + 1;
+            }
+        }
+        bounds
+    }
+
+    /// Packs `sprites` into one atlas using shelf packing, tallest first, with `padding` between them.
+    ///
+    /// Returns the atlas and each sprite's placement, in the same order as `sprites`.
+    pub fn pack_atlas(sprites: &[Bmp], padding: usize) -> (Bmp, Vec<BoundingBox>) {
+        // Aim for a roughly square atlas, but never narrower than the widest sprite
+        let area: usize = sprites
+            .iter()
+            .map(|sprite| (sprite.width + padding) * (sprite.height + padding))
+            .sum();
+        let widest = sprites.iter().map(|sprite| sprite.width).max().unwrap_or(0);
+        let shelf_width = widest.max(area.isqrt());
+
+        let mut order: Vec<usize> = (0..sprites.len()).collect();
+        order.sort_by_key(|&i| Reverse(sprites[i].height));
+
+        let mut placements = vec![BoundingBox::default(); sprites.len()];
+        let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+        let (mut width, mut height) = (0, 0);
+        for i in order {
+            let sprite = &sprites[i];
+            if x > 0 && x + sprite.width > shelf_width {
+                x = 0;
+                y += shelf_height + padding;
+                shelf_height = 0;
+            }
+            placements[i] = BoundingBox {
+                x1: x,
+                y1: y,
+                x2: x + sprite.width,
+                y2: y + sprite.height,
+            };
+            width = width.max(x + sprite.width);
+            height = height.max(y + sprite.height);
+            shelf_height = shelf_height.max(sprite.height);
+            x += sprite.width + padding;
+        }
+
+        let mut atlas = Bmp::new(width, height);
+        for (sprite, placement) in sprites.iter().zip(&placements) {
+            for row in 0..sprite.height {
+                let start = (placement.y1 + row) * width + placement.x1;
+                atlas.pixels[start..start + sprite.width]
+                    .copy_from_slice(&sprite.pixels[row * sprite.width..(row + 1) * sprite.width]);
+            }
+        }
+        (atlas, placements)
+    }
+
+    /// Mirrors the image left to right in place.
+    pub fn flip_horizontal(&mut self) {
+        for row in self.rows_mut() {
+            row.reverse();
+        }
+    }
+
+    /// Mirrors the image top to bottom in place by swapping whole rows.
+    pub fn flip_vertical(&mut self) {
+        let width = self.width;
+        for y in 0..self.height / 2 {
+            let (top, bottom) = self.pixels.split_at_mut((self.height - 1 - y) * width);
+            top[y * width..(y + 1) * width].swap_with_slice(&mut bottom[..width]);
+        }
+    }
+
+    /// Returns a copy rotated a quarter turn clockwise, with width and height swapped.
+    pub fn rotate90(&self) -> Bmp {
+        let h = self.height;
+        self.remapped(h, self.width, |x, y| (y, h - 1 - x))
+    }
+
+    /// Returns a copy rotated half a turn.
+    pub fn rotate180(&self) -> Bmp {
+        let mut rotated = self.clone();
+        rotated.rotate_180_in_place();
+        rotated
+    }
+
+    /// Returns a copy rotated a quarter turn counterclockwise, with width and height swapped.
+    pub fn rotate270(&self) -> Bmp {
+        let w = self.width;
+        self.remapped(self.height, w, |x, y| (w - 1 - y, x))
+    }
+
+    /// Returns a copy with the canvas grown so both dimensions are multiples of `multiple`,
+    /// keeping the image at the top-left and filling the new area with `fill`.
+    pub fn resize_canvas_to_multiple(&self, multiple: usize, fill: BMPixel) -> Bmp {
+        let multiple = multiple.max(1);
+        let width = self.width.next_multiple_of(multiple);
+        let height = self.height.next_multiple_of(multiple);
+        self.add_margin(0, width - self.width, 0, height - self.height, fill)
+    }
+
+    /// Replaces the alpha of every pixel with a linear ramp that is transparent at `edge` and
+    /// reaches opaque `width` pixels in, feathering that side of the image.
+    pub fn fade_edge(&mut self, edge: Edge, width: usize) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let distance = match edge {
+                    Edge::Top => y,
+                    Edge::Bottom => self.height - 1 - y,
+                    Edge::Left => x,
+                    Edge::Right => self.width - 1 - x,
+                };
+                let alpha = if distance >= width {
+                    255
+                } else {
+                    (distance * 255 / width) as u8
+                };
+                let index = y * self.width + x;
+                self.pixels[index] = self.pixels[index].with_alpha(alpha);
+            }
+        }
+    }
+
+    /// Copies the region inside `bounds` into a new image. The box must be non-empty and lie
+    /// inside the image.
+    pub fn crop(&self, bounds: BoundingBox) -> Result<Bmp, BmpError> {
+        if bounds.x1 >= bounds.x2
+            || bounds.y1 >= bounds.y2
+            || bounds.x2 > self.width
+            || bounds.y2 > self.height
+        {
+            return Err(BmpError::InvalidBounds);
+        }
+        Ok(
+            self.remapped(bounds.x2 - bounds.x1, bounds.y2 - bounds.y1, |x, y| {
+                (bounds.x1 + x, bounds.y1 + y)
+            }),
+        )
+    }
+
+    /// Returns a grayscale image where each pixel's brightness is its Euclidean RGB distance
+    /// from `reference`, scaled so the largest possible distance maps to white.
+    pub fn color_distance_map(&self, reference: BMPixel) -> Bmp {
+        let max_distance = (3.0f32 * 255.0 * 255.0).sqrt();
+        let pixels = self
+            .pixels
+            .iter()
+            .map(|pixel| {
+                let d = |a: u8, b: u8| (a as f32 - b as f32).powi(2);
+                let distance = (d(pixel.red(), reference.red())
+                    + d(pixel.green(), reference.green())
+                    + d(pixel.blue(), reference.blue()))
+                .sqrt();
+                let gray = (distance / max_distance * 255.0).round() as u32;
+                BMPixel(gray * 0x01_0101)
+            })
+            .collect();
+        Bmp {
+            width: self.width,
+            height: self.height,
+            pixels,
+        }
+    }
+
+    /// Returns a copy scaled to `new_width` x `new_height` with nearest-neighbor sampling.
+    pub fn resize_nearest(&self, new_width: usize, new_height: usize) -> Bmp {
+        if self.width == 0 || self.height == 0 {
+            return Bmp::new(new_width, new_height);
+        }
+        self.remapped(new_width, new_height, |x, y| {
+            (x * self.width / new_width, y * self.height / new_height)
+        })
+    }
+
+    /// Color grades the image through a 3D LUT, interpolating trilinearly; alpha is kept.
+    pub fn apply_lut_3d(&mut self, lut: &Lut3d) {
+        for pixel in self.pixels.iter_mut() {
+            *pixel = lut.apply(*pixel);
+        }
+    }
+
+    /// Returns a gray copy using Rec. 601 luma, `(77r + 150g + 29b) >> 8`, so pure red becomes
+    /// 76 and white stays 255. Alpha is kept.
+    pub fn to_grayscale(&self) -> Bmp {
+        let pixels = self
+            .pixels
+            .iter()
+            .map(|pixel| BMPixel(pixel.0 & 0xff00_0000 | (pixel.luminance() as u32 * 0x01_0101)))
+            .collect();
+        Bmp {
+            width: self.width,
+            height: self.height,
+            pixels,
+        }
+    }
+
+    /// Replaces each color channel with `255 - channel`, leaving alpha untouched.
+    pub fn invert(&mut self) {
+        for pixel in self.pixels.iter_mut() {
+            pixel.0 ^= 0x00ff_ffff;
+        }
+    }
+
+    /// Calls `f(y, row)` with each row of pixels in turn, top to bottom, for scanline effects.
+    pub fn apply_per_row<F: FnMut(usize, &mut [BMPixel])>(&mut self, mut f: F) {
+        for (y, row) in self.rows_mut().enumerate() {
+            f(y, row);
+        }
+    }
+
+    /// Strokes a Bresenham line from `(x0, y0)` to `(x1, y1)`, clipping points outside the image.
+    pub fn draw_line(&mut self, x0: usize, y0: usize, x1: usize, y1: usize, pixel: BMPixel) {
+        Self::bresenham(x0, y0, x1, y1, |x, y| {
+            if x < self.width && y < self.height {
+                self.pixels[y * self.width + x] = pixel;
+            }
+        });
+    }
+
+    /// Emulates CRT scanlines by darkening every `spacing`-th row by `intensity`, where 0 leaves
+    /// the row alone and 1 turns it black.
+    pub fn crt_scanlines(&mut self, intensity: f32, spacing: usize) {
+        if spacing == 0 {
+            return;
+        }
+        let factor = 1.0 - intensity.clamp(0.0, 1.0);
+        self.apply_per_row(|y, row| {
+            if (y + 1) % spacing == 0 {
+                for pixel in row.iter_mut() {
+                    *pixel = pixel.scaled(factor);
+                }
+            }
+        });
+    }
+
+    /// Outlines `bounds` with a 1px border, clipping any part of it outside the image.
+    pub fn draw_rect(&mut self, bounds: BoundingBox, pixel: BMPixel) {
+        if bounds.x1 >= bounds.x2 || bounds.y1 >= bounds.y2 {
+            return;
+        }
+        let edges = [
+            (bounds.x1, bounds.y1, bounds.x2, bounds.y1 + 1),
+            (bounds.x1, bounds.y2 - 1, bounds.x2, bounds.y2),
+            (bounds.x1, bounds.y1, bounds.x1 + 1, bounds.y2),
+            (bounds.x2 - 1, bounds.y1, bounds.x2, bounds.y2),
+        ];
+        for (x1, y1, x2, y2) in edges {
+            let edge = BoundingBox { x1, y1, x2, y2 }.clamped(self.width, self.height);
+            self.fill(edge, pixel);
+        }
+    }
+
+    /// Estimates the dominant gradient orientation in degrees, in `(-90, 90]`, measured from the
+    /// x axis towards increasing y. Sobel gradients of the luminance are averaged as doubled
+    /// angles weighted by magnitude, so opposite gradients along one edge reinforce each other.
+    pub fn dominant_gradient_direction(&self) -> f32 {
+        let luma = |x: usize, y: usize| self.pixels[y * self.width + x].luminance() as f32;
+        let (mut cos2, mut sin2) = (0.0f32, 0.0f32);
+        for y in 1..self.height.saturating_sub(1) {
+            for x in 1..self.width.saturating_sub(1) {
+                let gx = (luma(x + 1, y - 1) + 2.0 * luma(x + 1, y) + luma(x + 1, y + 1))
+                    - (luma(x - 1, y - 1) + 2.0 * luma(x - 1, y) + luma(x - 1, y + 1));
+                let gy = (luma(x - 1, y + 1) + 2.0 * luma(x, y + 1) + luma(x + 1, y + 1))
+                    - (luma(x - 1, y - 1) + 2.0 * luma(x, y - 1) + luma(x + 1, y - 1));
+                cos2 += gx * gx - gy * gy;
+                sin2 += 2.0 * gx * gy;
+            }
+        }
+        let angle = 0.5 * sin2.atan2(cos2).to_degrees();
+        if angle <= -90.0 {
+            angle + 180.0
+        } else {
+            angle
+        }
+    }
+
+    /// Replaces the 4-connected region of pixels matching the seed's color with `new_pixel`.
+    /// Uses an explicit stack, so large regions cannot overflow the call stack.
+    pub fn flood_fill(&mut self, x: usize, y: usize, new_pixel: BMPixel) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let target = self.pixels[y * self.width + x];
+        if target == new_pixel {
+            return;
+        }
+        let mut stack = vec![(x, y)];
+        while let Some((x, y)) = stack.pop() {
+            let index = y * self.width + x;
+            if self.pixels[index] != target {
+                continue;
+            }
+            self.pixels[index] = new_pixel;
+            if x > 0 {
+                stack.push((x - 1, y));
+            }
+            if x + 1 < self.width {
+                stack.push((x + 1, y));
+            }
+            if y > 0 {
+                stack.push((x, y - 1));
+            }
+            if y + 1 < self.height {
+                stack.push((x, y + 1));
+            }
+        }
+    }
+
+    /// Angle in degrees, within `[-45, 45]`, by which the dominant edges lean off the nearest axis.
+    fn skew_angle(&self) -> f32 {
+        let direction = self.dominant_gradient_direction();
+        direction - (direction / 90.0).round() * 90.0
+    }
+
+    /// Returns a copy rotated by `degrees` about the center, turning the x axis towards
+    /// increasing y, sampled bilinearly. Areas uncovered by the source become `background`.
+    fn rotated(&self, degrees: f32, background: BMPixel) -> Bmp {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let cx = (self.width as f32 - 1.0) / 2.0;
+        let cy = (self.height as f32 - 1.0) / 2.0;
+        let max_x = self.width as f32 - 1.0;
+        let max_y = self.height as f32 - 1.0;
+        let mut pixels = Vec::with_capacity(self.pixels.len());
+        for y in 0..self.height {
+            for x in 0..self.width {
+                // Rotate the destination back onto the source
+                let (dx, dy) = (x as f32 - cx, y as f32 - cy);
+                let sx = cx + dx * cos + dy * sin;
+                let sy = cy - dx * sin + dy * cos;
+                if !(0.0..=max_x).contains(&sx) || !(0.0..=max_y).contains(&sy) {
+                    pixels.push(background);
+                    continue;
+                }
+                let (x0, y0) = (sx.floor() as usize, sy.floor() as usize);
+                let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+                let (fx, fy) = (sx - x0 as f32, sy - y0 as f32);
+                let bytes = |x: usize, y: usize| self.pixels[y * self.width + x].0.to_le_bytes();
+                let (a, b, c, d) = (bytes(x0, y0), bytes(x1, y0), bytes(x0, y1), bytes(x1, y1));
+                let channel = |i: usize| {
+                    let top = a[i] as f32 + (b[i] as f32 - a[i] as f32) * fx;
+                    let bottom = c[i] as f32 + (d[i] as f32 - c[i] as f32) * fx;
+                    (top + (bottom - top) * fy).round() as u8
+                };
+                pixels.push(BMPixel(u32::from_le_bytes([
+                    channel(0),
+                    channel(1),
+                    channel(2),
+                    channel(3),
+                ])));
+            }
+        }
+        Bmp {
+            width: self.width,
+            height: self.height,
+            pixels,
+        }
+    }
+
+    /// Straightens a skewed scan: estimates how far its edges lean off the axes from the
+    /// dominant gradient direction and rotates that back, filling the corners with `background`.
+    pub fn auto_deskew(&self, background: BMPixel) -> Bmp {
+        self.rotated(-self.skew_angle(), background)
+    }
+
+    /// Fills the disk of pixels within `radius` of `(cx, cy)`, clipping it to the image.
+    pub fn draw_circle(&mut self, cx: usize, cy: usize, radius: usize, pixel: BMPixel) {
+        let x_range = cx.saturating_sub(radius)..(cx + radius + 1).min(self.width);
+        for y in cy.saturating_sub(radius)..(cy + radius + 1).min(self.height) {
+            for x in x_range.clone() {
+                let (dx, dy) = (x.abs_diff(cx), y.abs_diff(cy));
+                if dx * dx + dy * dy <= radius * radius {
+                    self.pixels[y * self.width + x] = pixel;
+                }
+            }
+        }
+    }
+
+    /// Maps each pixel's luminance onto the gradient from `shadow` (black) to `highlight`
+    /// (white), keeping the pixel's alpha.
+    pub fn duotone(&mut self, shadow: BMPixel, highlight: BMPixel) {
+        for pixel in self.pixels.iter_mut() {
+            let t = pixel.luminance() as f32 / 255.0;
+            *pixel = shadow.blend(highlight, t).with_alpha(pixel.alpha());
+        }
+    }
+
+    /// Iterates over the rows of the image, top to bottom, each a slice of `width` pixels.
+    pub fn rows(&self) -> impl DoubleEndedIterator<Item = &[BMPixel]> {
+        // A zero-width image has no pixels, so any nonzero chunk size yields nothing
+        self.pixels.chunks_exact(self.width.max(1))
+    }
+
+    /// Mutable counterpart of [`Bmp::rows`].
+    pub fn rows_mut(&mut self) -> impl DoubleEndedIterator<Item = &mut [BMPixel]> {
+        self.pixels.chunks_exact_mut(self.width.max(1))
+    }
+
+    /// Splits the image into tightly packed, top-down red, green, and blue byte planes.
+    pub fn to_planar(&self) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        let red = self.pixels.iter().map(BMPixel::red).collect();
+        let green = self.pixels.iter().map(BMPixel::green).collect();
+        let blue = self.pixels.iter().map(BMPixel::blue).collect();
+        (red, green, blue)
+    }
+
+    /// Iterates over `(x, y, pixel)` for every pixel in row-major order.
+    pub fn enumerate_pixels(&self) -> impl Iterator<Item = (usize, usize, BMPixel)> + '_ {
+        let width = self.width;
+        self.pixels
+            .iter()
+            .enumerate()
+            .map(move |(i, &pixel)| (i % width, i / width, pixel))
+    }
+
+    /// Mutable counterpart of [`Bmp::enumerate_pixels`].
+    pub fn enumerate_pixels_mut(&mut self) -> impl Iterator<Item = (usize, usize, &mut BMPixel)> {
+        let width = self.width;
+        self.pixels
+            .iter_mut()
+            .enumerate()
+            .map(move |(i, pixel)| (i % width, i / width, pixel))
+    }
+
+    /// Interleaves separate red, green, and blue planes, each `width * height` bytes with the top
+    /// row first, into an image.
+    pub fn from_planar(
+        width: usize,
+        height: usize,
+        r: &[u8],
+        g: &[u8],
+        b: &[u8],
+    ) -> Result<Bmp, BmpError> {
+        let expected = width * height;
+        if let Some(plane) = [r, g, b].into_iter().find(|plane| plane.len() != expected) {
+            return Err(BmpError::InvalidLength {
+                expected,
+                found: plane.len(),
+            });
+        }
+        let pixels = (0..expected)
+            .map(|i| BMPixel::from_rgba(r[i], g[i], b[i], 0))
+            .collect();
+        Ok(Bmp {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// Fills the image with a radial gradient from `inner` at `(cx, cy)` to `outer` at `radius`
+    /// pixels away, staying `outer` beyond that.
+    pub fn fill_radial_gradient(
+        &mut self,
+        cx: usize,
+        cy: usize,
+        radius: usize,
+        inner: BMPixel,
+        outer: BMPixel,
+    ) {
+        for (x, y, pixel) in self.enumerate_pixels_mut() {
+            let dx = x.abs_diff(cx) as f32;
+            let dy = y.abs_diff(cy) as f32;
+            let distance = (dx * dx + dy * dy).sqrt();
+            let t = if radius == 0 {
+                1.0
+            } else {
+                distance / radius as f32
+            };
+            *pixel = inner.blend(outer, t);
+        }
+    }
+
+    /// Encodes the image with [`Bmp::to_bytes`] and decodes it with [`Bmp::from_bytes`],
+    /// returning true if the result has the same size and colors. Alpha is ignored, since the
+    /// 24-bit format cannot hold it.
+    pub fn verify_roundtrip(&self) -> bool {
+        Bmp::from_bytes(&self.to_bytes()).is_ok_and(|decoded| {
+            decoded.width == self.width
+                && decoded.height == self.height
+                && decoded
+                    .pixels
+                    .iter()
+                    .zip(&self.pixels)
+                    .all(|(a, b)| (a.0 ^ b.0) & 0x00ff_ffff == 0)
+        })
+    }
+
+    /// Copies a `w` x `h` tile starting at `(x, y)`, which may be negative, wrapping around the
+    /// edges so the image repeats seamlessly. An empty image gives an empty tile.
+    pub fn extract_tile(&self, x: isize, y: isize, w: usize, h: usize) -> Bmp {
+        if self.width == 0 || self.height == 0 {
+            return Bmp::new(w, h);
+        }
+        let wrap = |start: isize, offset: usize, len: usize| {
+            (start + offset as isize).rem_euclid(len as isize) as usize
+        };
+        self.remapped(w, h, |dx, dy| {
+            (wrap(x, dx, self.width), wrap(y, dy, self.height))
+        })
+    }
+
+    /// Quantizes to `palette` with ordered dithering: each pixel is nudged by its cell of a 4x4
+    /// Bayer matrix before snapping to the nearest entry, giving a stable, tileable pattern.
+    /// Alpha is kept.
+    pub fn dither_ordered(&mut self, palette: &[BMPixel]) {
+        const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+        for (x, y, pixel) in self.enumerate_pixels_mut() {
+            let offset = 255.0 * ((BAYER[y % 4][x % 4] as f32 + 0.5) / 16.0 - 0.5);
+            let nudge = |channel: u8| (channel as f32 + offset).round().clamp(0.0, 255.0) as u8;
+            let nudged = BMPixel::from_rgba(
+                nudge(pixel.red()),
+                nudge(pixel.green()),
+                nudge(pixel.blue()),
+                0,
+            );
+            if let Some(nearest) = nudged.nearest_color(palette) {
+                pixel.0 = (pixel.0 & 0xff00_0000) | (nearest.0 & 0x00ff_ffff);
+            }
+        }
+    }
+
+    /// Copies the disk of `radius` around `(src_x, src_y)` onto the disk around `(dst_x, dst_y)`,
+    /// like a clone brush. Pixels whose source or destination falls outside the image are skipped.
+    pub fn clone_stamp(
+        &mut self,
+        src_x: usize,
+        src_y: usize,
+        dst_x: usize,
+        dst_y: usize,
+        radius: usize,
+    ) {
+        // Read from a snapshot so overlapping source and destination disks don't smear
+        let source = self.pixels.clone();
+        let (width, height) = (self.width as isize, self.height as isize);
+        let index = |x: isize, y: isize| {
+            (x >= 0 && y >= 0 && x < width && y < height).then(|| (y * width + x) as usize)
+        };
+        let r = radius as isize;
+        for dy in -r..=r {
+            for dx in -r..=r {
+                if dx * dx + dy * dy > r * r {
+                    continue;
+                }
+                let from = index(src_x as isize + dx, src_y as isize + dy);
+                let to = index(dst_x as isize + dx, dst_y as isize + dy);
+                if let (Some(from), Some(to)) = (from, to) {
+                    self.pixels[to] = source[from];
+                }
+            }
+        }
+    }
+
+    /// Maps local structural similarity (SSIM) to `other` as a grayscale image: each pixel
+    /// compares the luminance of the 7x7 windows around it, clipped to the image, with white
+    /// for identical structure and black for none or inverted.
+    pub fn ssim_map(&self, other: &Bmp) -> Result<Bmp, BmpError> {
+        self.check_same_dimensions(other)?;
+
+        const RADIUS: usize = 3;
+        const C1: f32 = (0.01 * 255.0) * (0.01 * 255.0);
+        const C2: f32 = (0.03 * 255.0) * (0.03 * 255.0);
+        let luma = |bmp: &Bmp, x: usize, y: usize| bmp.pixels[y * bmp.width + x].luminance() as f32;
+
+        let mut pixels = Vec::with_capacity(self.pixels.len());
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let ys = y.saturating_sub(RADIUS)..(y + RADIUS + 1).min(self.height);
+                let xs = x.saturating_sub(RADIUS)..(x + RADIUS + 1).min(self.width);
+                let n = (ys.len() * xs.len()) as f32;
+                let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) =
+                    (0.0, 0.0, 0.0, 0.0, 0.0);
+                for wy in ys {
+                    for wx in xs.clone() {
+                        let (a, b) = (luma(self, wx, wy), luma(other, wx, wy));
+                        sum_a += a;
+                        sum_b += b;
+                        sum_aa += a * a;
+                        sum_bb += b * b;
+                        sum_ab += a * b;
+                    }
+                }
+                let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+                let var_a = sum_aa / n - mean_a * mean_a;
+                let var_b = sum_bb / n - mean_b * mean_b;
+                let covariance = sum_ab / n - mean_a * mean_b;
+                let ssim = ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+                    / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+                let gray = (ssim.clamp(0.0, 1.0) * 255.0).round() as u32;
+                pixels.push(BMPixel(gray * 0x01_0101));
+            }
+        }
+        Ok(Bmp {
+            width: self.width,
+            height: self.height,
+            pixels,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BoundingBox {
+    pub x1: usize,
+    pub y1: usize,
+    pub x2: usize,
+    pub y2: usize,
+}
+
+impl BoundingBox {
+    /// Shrinks the box to fit inside a `width` x `height` image; the result may be empty.
+    pub(crate) fn clamped(self, width: usize, height: usize) -> BoundingBox {
+        let x2 = self.x2.min(width);
+        let y2 = self.y2.min(height);
+        BoundingBox {
+            x1: self.x1.min(x2),
+            y1: self.y1.min(y2),
+            x2,
+            y2,
+        }
+    }
+}
+
+/// Per-channel formulas used by [`Bmp::composite`], with the base image as `a` and the layer as `b`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendMode {
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Add,
+    Darken,
+    Lighten,
+}
+
+impl BlendMode {
+    fn blend_channel(self, a: u8, b: u8) -> u8 {
+        let (a, b) = (a as u32, b as u32);
+        let blended = match self {
+            BlendMode::Normal => b,
+            BlendMode::Multiply => (a * b + 127) / 255,
+            BlendMode::Screen => 255 - ((255 - a) * (255 - b) + 127) / 255,
+            BlendMode::Overlay if a < 128 => (2 * a * b + 127) / 255,
+            BlendMode::Overlay => 255 - (2 * (255 - a) * (255 - b) + 127) / 255,
+            BlendMode::Add => (a + b).min(255),
+            BlendMode::Darken => a.min(b),
+            BlendMode::Lighten => a.max(b),
+        };
+        blended as u8
+    }
+
+    fn blend(self, a: BMPixel, b: BMPixel) -> BMPixel {
+        let r = self.blend_channel(a.red(), b.red()) as u32;
+        let g = self.blend_channel(a.green(), b.green()) as u32;
+        let bl = self.blend_channel(a.blue(), b.blue()) as u32;
+        BMPixel((a.0 & 0xff00_0000) | r << 16 | g << 8 | bl)
+    }
+}
+
+/// A side of the image, as used by [`Bmp::fade_edge`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+/// The eight EXIF orientations, named after the transform that displays the image upright.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    Normal,
+    FlipHorizontal,
+    Rotate180,
+    FlipVertical,
+    Transpose,
+    Rotate90,
+    Transverse,
+    Rotate270,
+}
+
+impl Orientation {
+    /// Maps the EXIF orientation tag (1..=8) to its variant.
+    pub fn from_exif(value: u16) -> Option<Orientation> {
+        let orientation = match value {
+            1 => Orientation::Normal,
+            2 => Orientation::FlipHorizontal,
+            3 => Orientation::Rotate180,
+            4 => Orientation::FlipVertical,
+            5 => Orientation::Transpose,
+            6 => Orientation::Rotate90,
+            7 => Orientation::Transverse,
+            8 => Orientation::Rotate270,
+            _ => return None,
+        };
+        Some(orientation)
+    }
+}
+
+/// Minimum, maximum, mean, and population standard deviation of one channel.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ChannelSummary {
+    pub min: u8,
+    pub max: u8,
+    pub mean: f64,
+    pub std_dev: f64,
+}
+
+/// Per-channel statistics returned by [`Bmp::channel_statistics`].
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ChannelStats {
+    pub red: ChannelSummary,
+    pub green: ChannelSummary,
+    pub blue: ChannelSummary,
+}
+
+/// Welford's online mean and variance, which avoids the cancellation of sum-of-squares.
+#[derive(Default)]
+struct RunningStats {
+    count: f64,
+    min: u8,
+    max: u8,
+    mean: f64,
+    m2: f64,
+}
+
+impl RunningStats {
+    fn push(&mut self, value: u8) {
+        if self.count == 0.0 {
+            (self.min, self.max) = (value, value);
+        }
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.count += 1.0;
+        let delta = value as f64 - self.mean;
+        self.mean += delta / self.count;
+        self.m2 += delta * (value as f64 - self.mean);
+    }
+
+    fn summary(&self) -> ChannelSummary {
+        let variance = if self.count == 0.0 {
+            0.0
+        } else {
+            self.m2 / self.count
+        };
+        ChannelSummary {
+            min: self.min,
+            max: self.max,
+            mean: self.mean,
+            std_dev: variance.sqrt(),
+        }
+    }
+}
+
+/// A borrowed rectangular region of a [`Bmp`], addressed relative to its top-left corner.
+#[derive(Debug, Clone, Copy)]
+pub struct SubImage<'a> {
+    bmp: &'a Bmp,
+    bounds: BoundingBox,
+}
+
+impl SubImage<'_> {
+    pub fn width(&self) -> usize {
+        self.bounds.x2 - self.bounds.x1
+    }
+
+    pub fn height(&self) -> usize {
+        self.bounds.y2 - self.bounds.y1
+    }
+
+    /// Returns the pixel at `(x, y)` within the view, or `None` outside of it.
+    pub fn get_pixel(&self, x: usize, y: usize) -> Option<BMPixel> {
+        if x >= self.width() || y >= self.height() {
+            return None;
+        }
+        Some(self.bmp.pixels[(self.bounds.y1 + y) * self.bmp.width + self.bounds.x1 + x])
+    }
+}
+
+impl Bmp {
+    /// Reads the ode5 bitmap file.
+    pub fn read_to_bmp(file_path: &Path) -> Result<Self, BmpError> {
+        let bytes = std::fs::read(file_path)?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Reads a BMP from a source that cannot seek by buffering the whole stream first.
+    pub fn from_read_no_seek<R: Read>(mut reader: R) -> Result<Bmp, BmpError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Reads the bitmap file one row at a time, calling `progress` with the fraction of rows
+    /// decoded so far, ending at 1.0. RLE8 runs can cross rows, so compressed files are decoded
+    /// in one go and report only completion.
+    pub fn read_to_bmp_with_progress<F: FnMut(f32)>(
+        path: &Path,
+        mut progress: F,
+    ) -> Result<Bmp, BmpError> {
+        // A file that ends early is not a BMP if it ends in the headers, and truncated otherwise
+        let eof_as = |error: BmpError| {
+            move |err: std::io::Error| match err.kind() {
+                ErrorKind::UnexpectedEof => error,
+                _ => BmpError::Io(err),
+            }
+        };
+        let file = File::open(path)?;
+        let file_len = file.metadata()?.len() as usize;
+        let mut reader = BufReader::new(file);
+
+        // Everything before the pixel data is small: the headers and the color table
+        let mut head =
+            vec![0u8; std::mem::size_of::<FileHeader>() + std::mem::size_of::<InfoHeader>()];
+        reader
+            .read_exact(&mut head)
+            .map_err(eof_as(BmpError::NotBmp))?;
+        let (file_header, info_header) = Self::parse_headers(&head)?;
+        let offset = file_header.bfOffBits as usize;
+        // Header fields size the buffers below, so check them against the file before allocating
+        if offset > file_len {
+            return Err(BmpError::TruncatedPixelData);
+        }
+        let header_len = head.len();
+        head.resize(offset, 0);
+        reader
+            .read_exact(&mut head[header_len..])
+            .map_err(eof_as(BmpError::TruncatedPixelData))?;
+        Self::check_bitfields(&head, &file_header, &info_header)?;
+
+        if info_header.biCompression == BI_RLE8 {
+            let mut bytes = head;
+            reader.read_to_end(&mut bytes)?;
+            let bmp = Self::from_bytes(&bytes)?;
+            progress(1.0);
+            return Ok(bmp);
+        }
+
+        // Validated like from_bytes, though the rows are then read one at a time
+        Self::pixel_data_size(&info_header, file_len - offset)?;
+        let width = info_header.biWidth as usize;
+        let height = info_header.biHeight.unsigned_abs() as usize;
+        let palette = Self::palette(&head, &file_header, &info_header);
+        let mut row = vec![0u8; calculate_stride(width, info_header.biBitCount)];
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            reader
+                .read_exact(&mut row)
+                .map_err(eof_as(BmpError::TruncatedPixelData))?;
+            if info_header.biBitCount <= 8 {
+                pixels.extend(
+                    Self::decode_indices(&row, width, 1, info_header.biBitCount)?
+                        .into_iter()
+                        .map(|index| Self::lookup(&palette, index)),
+                );
+            } else {
+                pixels.extend(Self::decode_pixels(&row, width, 1, info_header.biBitCount));
+            }
+            progress((y + 1) as f32 / height as f32);
+        }
+        if height == 0 {
+            progress(1.0);
+        }
+
+        let mut bmp = Self {
+            width,
+            height,
+            pixels,
+        };
+        if info_header.biHeight > 0 {
+            bmp.flip_vertical();
+        }
+        Ok(bmp)
+    }
+
+    /// Decodes a BMP held in memory, validating it the same way the file reader does.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Bmp, BmpError> {
+        let (file_header, info_header) = Self::parse_headers(bytes)?;
+        Self::check_bitfields(bytes, &file_header, &info_header)?;
+
+        let width = info_header.biWidth as usize;
+        let height = info_header.biHeight.unsigned_abs() as usize;
+        let top_down = info_header.biHeight < 0;
+
+        // The offset replaces the seek: slice from bfOffBits instead
+        let remaining = bytes
+            .get(file_header.bfOffBits as usize..)
+            .ok_or(BmpError::TruncatedPixelData)?;
+        let data = &remaining[..Self::pixel_data_size(&info_header, remaining.len())?];
+
+        let pixels = if info_header.biBitCount <= 8 {
+            let palette = Self::palette(bytes, &file_header, &info_header);
+            let indices = if info_header.biCompression == BI_RLE8 {
+                decode_rle8(data, width, height)?
+            } else {
+                Self::decode_indices(data, width, height, info_header.biBitCount)?
+            };
+            indices
+                .into_iter()
+                .map(|index| Self::lookup(&palette, index))
+                .collect()
+        } else {
+            Self::decode_pixels(data, width, height, info_header.biBitCount)
+        };
+
+        let mut bmp = Self {
+            width,
+            height,
+            pixels,
+        };
+        // Rows are kept top-down, so the usual bottom-up files are flipped to match
+        if !top_down {
+            bmp.flip_vertical();
+        }
+        Ok(bmp)
+    }
+
+    /// Reads the file and info headers from the start of `bytes` and checks that the format is
+    /// one the decoders handle, with pixel data starting after the headers.
+    fn parse_headers(bytes: &[u8]) -> Result<(FileHeader, InfoHeader), BmpError> {
+        let file_header_size = std::mem::size_of::<FileHeader>();
+        let info_header_size = std::mem::size_of::<InfoHeader>();
+        if bytes.len() < file_header_size + info_header_size {
+            return Err(BmpError::NotBmp);
+        }
+
+        let file_header = FileHeader::from_bytes(&bytes[..file_header_size]);
+        if file_header._bfType != [0x42, 0x4D] {
+            return Err(BmpError::NotBmp);
+        }
+
+        let info_header =
+            InfoHeader::from_bytes(&bytes[file_header_size..file_header_size + info_header_size]);
+        // Pixel data that starts inside the headers would decode header bytes as pixels
+        let headers_end = file_header_size + (info_header.biSize as usize).max(info_header_size);
+        if (file_header.bfOffBits as usize) < headers_end {
+            return Err(BmpError::NotBmp);
+        }
+        match (info_header.biBitCount, info_header.biCompression) {
+            (1 | 4 | 8 | 24 | 32, 0) | (8, BI_RLE8) | (32, BI_BITFIELDS) => {}
+            (bit_count, 0 | BI_RLE8 | BI_BITFIELDS) => {
+                return Err(BmpError::UnsupportedBitCount(bit_count))
+            }
+            (_, compression) => return Err(BmpError::UnsupportedCompression(compression)),
+        }
+        Ok((file_header, info_header))
+    }
+
+    /// Works out how many bytes of pixel data to decode when `available` bytes follow the
+    /// offset, failing if the file cannot back them.
+    fn pixel_data_size(info_header: &InfoHeader, available: usize) -> Result<usize, BmpError> {
+        let width = info_header.biWidth as usize;
+        let height = info_header.biHeight.unsigned_abs() as usize;
+        // Never trust biSizeImage blindly: uncompressed data is sliced to the computed size, and a
+        // declared size beyond both that and the file is rejected outright
+        let rle = info_header.biCompression == BI_RLE8;
+        let size_image = match info_header.biSizeImage as usize {
+            0 if rle => available,
+            declared if rle => declared,
+            declared => {
+                // Dimensions too large to even size cannot be backed by the file
+                let computed = checked_image_size(width, height, info_header.biBitCount)
+                    .ok_or(BmpError::TruncatedPixelData)?;
+                if declared > computed && declared > available {
+                    return Err(BmpError::SizeMismatch {
+                        declared,
+                        expected: computed,
+                    });
+                }
+                // Uncompressed rows have a known size; some writers store it without row padding
+                computed
+            }
+        };
+        if size_image > available {
+            return Err(BmpError::TruncatedPixelData);
+        }
+        Ok(size_image)
+    }
+
+    /// Bitfield pixels are only decoded when their masks describe plain BGRA. The alpha mask is
+    /// part of the V4 and V5 headers only, and the masks follow a 40-byte header directly.
+    fn check_bitfields(
+        bytes: &[u8],
+        file_header: &FileHeader,
+        info_header: &InfoHeader,
+    ) -> Result<(), BmpError> {
+        if info_header.biCompression != BI_BITFIELDS {
+            return Ok(());
+        }
+        let start = std::mem::size_of::<FileHeader>() + std::mem::size_of::<InfoHeader>();
+        let count = if info_header.biSize >= 56 { 4 } else { 3 };
+        let masks: Vec<u32> = bytes
+            .get(start..file_header.bfOffBits as usize)
+            .unwrap_or_default()
+            .chunks_exact(4)
+            .take(count)
+            .map(|mask| u32::from_le_bytes([mask[0], mask[1], mask[2], mask[3]]))
+            .collect();
+        match masks.as_slice() {
+            [red, green, blue, alpha @ ..]
+                if [*red, *green, *blue] == BGRA_MASKS[..3]
+                    && matches!(alpha, [] | [0] | [0xff00_0000]) =>
+            {
+                Ok(())
+            }
+            _ => Err(BmpError::UnsupportedCompression(BI_BITFIELDS)),
+        }
+    }
+
+    /// Decodes the color table, which sits between the info header and the pixel data.
+    fn palette(bytes: &[u8], file_header: &FileHeader, info_header: &InfoHeader) -> Vec<BMPixel> {
+        let palette_start = std::mem::size_of::<FileHeader>() + info_header.biSize as usize;
+        bytes
+            .get(palette_start..file_header.bfOffBits as usize)
+            .map(|table| decode_palette(table, info_header.palette_len()))
+            .unwrap_or_default()
+    }
+
+    /// Maps a palette index to its color; indices past the table are black.
+    fn lookup(palette: &[BMPixel], index: u8) -> BMPixel {
+        palette
+            .get(index as usize)
+            .copied()
+            .unwrap_or(BMPixel::EMPTY)
+    }
+
+    /// Reads 1-, 4- or 8-bit palette indices, packed most significant bits first, from rows
+    /// padded to 4 bytes.
+    fn decode_indices(
+        data: &[u8],
+        width: usize,
+        height: usize,
+        bit_count: u16,
+    ) -> Result<Vec<u8>, BmpError> {
+        let stride = calculate_stride(width, bit_count);
+        if data.len() < stride * height {
+            return Err(BmpError::TruncatedPixelData);
+        }
+        let bits = bit_count as usize;
+        let mask = ((1u16 << bits) - 1) as u8;
+        Ok(data
+            .chunks_exact(stride.max(1))
+            .take(height)
+            .flat_map(|row| {
+                (0..width).map(move |x| {
+                    let bit = x * bits;
+                    (row[bit / 8] >> (8 - bits - bit % 8)) & mask
+                })
+            })
+            .collect())
+    }
+
+    /// Reads 24-bit BGR or 32-bit BGRA pixels; 24-bit pixels get an alpha of 0.
+    fn decode_pixels(data: &[u8], width: usize, height: usize, bit_count: u16) -> Vec<BMPixel> {
+        let bytes_per_row = calculate_stride(width, bit_count);
+        let bytes_per_pixel = bit_count as usize / 8;
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let data_index = y * bytes_per_row + x * bytes_per_pixel;
+                let b = data[data_index];
+                let g = data[data_index + 1];
+                let r = data[data_index + 2];
+                let a = if bytes_per_pixel == 4 {
+                    data[data_index + 3]
+                } else {
+                    0
+                };
+                pixels.push(BMPixel::from_rgba(r, g, b, a));
+            }
+        }
+        pixels
+    }
+
+    pub fn write_to_file(&self, file_path: &Path) -> Result<(), std::io::Error> {
+        let mut file = File::create(file_path).expect("Unable to create file");
+
+        file.write_all(&self.to_bytes())
+    }
+
+    /// Encodes the bitmap as the bytes of a 24-bit BMP file, without touching the filesystem.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        Ode5Bmp::new(self).to_bytes()
+    }
+
+    /// Writes the bitmap with a 124-byte V5 header tagged as sRGB, for color-managed consumers.
+    pub fn write_v5(&self, file_path: &Path) -> Result<(), BmpError> {
+        let ode5bmp = Ode5Bmp::new(self);
+        let mut file = File::create(file_path)?;
+        file.write_all(&ode5bmp.to_bytes_v5())?;
+        Ok(())
+    }
+}
+
+/// Indexes pixels by `(x, y)`, the same order as [`Bmp::set_pixel`]; panics out of bounds.
+impl Index<(usize, usize)> for Bmp {
+    type Output = BMPixel;
+
+    fn index(&self, (x, y): (usize, usize)) -> &BMPixel {
+        assert!(
+            x < self.width && y < self.height,
+            "pixel ({x}, {y}) out of bounds for {}x{} image",
+            self.width,
+            self.height
+        );
+        &self.pixels[y * self.width + x]
+    }
+}
+
+impl IndexMut<(usize, usize)> for Bmp {
+    fn index_mut(&mut self, (x, y): (usize, usize)) -> &mut BMPixel {
+        assert!(
+            x < self.width && y < self.height,
+            "pixel ({x}, {y}) out of bounds for {}x{} image",
+            self.width,
+            self.height
+        );
+        &mut self.pixels[y * self.width + x]
+    }
+}
+
+#[cfg(feature = "image")]
+impl From<image::RgbImage> for Bmp {
+    fn from(image: image::RgbImage) -> Self {
+        let (width, height) = image.dimensions();
+        // Both store rows top-down, so they copy across without flipping
+        let pixels = image
+            .pixels()
+            .map(|&image::Rgb([r, g, b])| BMPixel::from_rgba(r, g, b, 0))
+            .collect();
+        Bmp {
+            width: width as usize,
+            height: height as usize,
+            pixels,
+        }
+    }
+}
+
+#[cfg(feature = "image")]
+impl From<&Bmp> for image::RgbImage {
+    fn from(bmp: &Bmp) -> Self {
+        image::RgbImage::from_fn(bmp.width as u32, bmp.height as u32, |x, y| {
+            let pixel = bmp.pixels[y as usize * bmp.width + x as usize];
+            image::Rgb([pixel.red(), pixel.green(), pixel.blue()])
+        })
+    }
+}
+
+/// Counts the bits that differ between two perceptual hashes such as [`Bmp::dhash`].
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("data/greenblue_square-1794933754679872826.bmp", 12, 12)]
+    #[case("data/france-7921693104947760092.bmp", 30, 20)]
+    #[case("data/handcrafted-2044735835957623026.bmp", 5, 5)]
+    fn test_read_bmp(#[case] file_path: &str, #[case] width: usize, #[case] height: usize) {
+        let bmp = Bmp::read_to_bmp(Path::new(file_path)).unwrap();
+        assert_eq!(bmp.width, width);
+        assert_eq!(bmp.height, height);
+    }
+
+    #[test]
+    fn test_read_bmp_pixels() {
+        let bmp = Bmp::read_to_bmp(Path::new("data/test.bmp")).unwrap();
+        assert_eq!(bmp.width, 45);
+        assert_eq!(bmp.height, 30);
+        assert_eq!(bmp.pixels[0], BMPixel(0x00_00ff));
+    }
+
+    #[test]
+    fn test_write_bmp() {
+        let mut bmp = Bmp::new(45, 30);
+        bmp.fill(
+            BoundingBox {
+                x1: 0,
+                y1: 0,
+                x2: 45,
+                y2: 30,
+            },
+            BMPixel(0x00_00ff),
+        );
+        bmp.write_to_file(Path::new("data/test.bmp")).unwrap();
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let bmp =
+            Bmp::read_to_bmp(Path::new("data/greenblue_square-1794933754679872826.bmp")).unwrap();
+        bmp.write_to_file(Path::new(
+            "data/tmp-greenblue_square-1794933754679872826.bmp",
+        ))
+        .unwrap();
+        let bmp2 = Bmp::read_to_bmp(Path::new(
+            "data/tmp-greenblue_square-1794933754679872826.bmp",
+        ))
+        .unwrap();
+        assert_eq!(bmp, bmp2);
+    }
+
+    #[test]
+    fn test_read_bmp_not_bmp() {
+        let result = Bmp::read_to_bmp(Path::new("data/not-a-bmp.txt"));
+        assert!(matches!(result, Err(BmpError::NotBmp)));
+    }
+
+    #[test]
+    fn test_tint() {
+        let mut bmp = Bmp::new(2, 1);
+        bmp.set_pixel(0, 0, BMPixel(0x12_3456));
+        bmp.set_pixel(1, 0, BMPixel(0x80_ff40));
+        let original = bmp.clone();
+
+        bmp.tint(BMPixel(0xff_ffff));
+        assert_eq!(bmp, original);
+
+        bmp.tint(BMPixel(0xff_0000));
+        assert_eq!(bmp.pixels, vec![BMPixel(0x12_0000), BMPixel(0x80_0000)]);
+    }
+
+    #[test]
+    fn test_diff_regions() {
+        let before = Bmp::new(10, 10);
+        let mut after = before.clone();
+        after.fill(
+            BoundingBox {
+                x1: 1,
+                y1: 1,
+                x2: 3,
+                y2: 3,
+            },
+            BMPixel(0xff_ffff),
+        );
+        after.set_pixel(7, 8, BMPixel(0x00_0080));
+        // Below the threshold, so not reported
+        after.set_pixel(9, 0, BMPixel(0x00_0004));
+
+        let regions = before.diff_regions(&after, 8).unwrap();
+        assert_eq!(
+            regions,
+            vec![
+                BoundingBox {
+                    x1: 1,
+                    y1: 1,
+                    x2: 3,
+                    y2: 3
+                },
+                BoundingBox {
+                    x1: 7,
+                    y1: 8,
+                    x2: 8,
+                    y2: 9
+                },
+            ]
+        );
+        assert!(before.diff_regions(&Bmp::new(5, 5), 0).is_err());
+    }
+
+    #[test]
+    fn test_row_hashes() {
+        let bmp = Bmp::read_to_bmp(Path::new("data/france-7921693104947760092.bmp")).unwrap();
+        let mut changed = bmp.clone();
+        changed.set_pixel(4, 7, BMPixel(changed.pixels[7 * 30 + 4].0 ^ 0x01));
+
+        let before = bmp.row_hashes();
+        let after = changed.row_hashes();
+        assert_eq!(before.len(), 20);
+        let differing: Vec<usize> = (0..before.len())
+            .filter(|&y| before[y] != after[y])
+            .collect();
+        assert_eq!(differing, vec![7]);
+    }
+
+    /// Only implements `Read`, like a pipe or decompressor would.
+    struct NoSeek<R>(R);
+
+    impl<R: Read> Read for NoSeek<R> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    #[test]
+    fn test_from_read_no_seek() {
+        let path = Path::new("data/greenblue_square-1794933754679872826.bmp");
+        let reader = NoSeek(File::open(path).unwrap());
+        let bmp = Bmp::from_read_no_seek(reader).unwrap();
+        assert_eq!(bmp, Bmp::read_to_bmp(path).unwrap());
+
+        let not_bmp = NoSeek(&b"hello, this is definitely not a bitmap file at all......"[..]);
+        assert!(matches!(
+            Bmp::from_read_no_seek(not_bmp),
+            Err(BmpError::NotBmp)
+        ));
+    }
+
+    #[test]
+    fn test_vignette() {
+        let mut bmp = Bmp::new(5, 5);
+        bmp.fill(
+            BoundingBox {
+                x1: 0,
+                y1: 0,
+                x2: 5,
+                y2: 5,
+            },
+            BMPixel(0xc8_c8c8),
+        );
+        bmp.vignette(0.5);
+        assert_eq!(bmp.pixels[2 * 5 + 2], BMPixel(0xc8_c8c8));
+        for (x, y) in [(0, 0), (4, 0), (0, 4), (4, 4)] {
+            assert_eq!(bmp.pixels[y * 5 + x], BMPixel(0x64_6464));
+        }
+    }
+
+    #[test]
+    fn test_pixelate() {
+        let mut bmp = Bmp::new(4, 4);
+        for (i, pixel) in bmp.pixels.iter_mut().enumerate() {
+            *pixel = BMPixel(if i % 2 == 0 { 0x00_0000 } else { 0x64_c8fa });
+        }
+        bmp.pixelate(4);
+        assert!(bmp.pixels.iter().all(|&pixel| pixel == BMPixel(0x32_647d)));
+
+        // Partial edge blocks are averaged on their own
+        let mut bmp = Bmp::new(3, 1);
+        bmp.pixels = vec![BMPixel(0x00_0010), BMPixel(0x00_0030), BMPixel(0x00_0080)];
+        bmp.pixelate(2);
+        assert_eq!(
+            bmp.pixels,
+            vec![BMPixel(0x00_0020), BMPixel(0x00_0020), BMPixel(0x00_0080)]
+        );
+
+        // Saturated channels average back to themselves
+        let mut bmp = Bmp::new(3, 3);
+        bmp.pixels.fill(BMPixel(0xff_ffff));
+        bmp.pixelate(3);
+        assert!(bmp.pixels.iter().all(|&pixel| pixel == BMPixel(0xff_ffff)));
+    }
+
+    #[test]
+    fn test_convolve_separable_matches_2d() {
+        let bmp =
+            Bmp::read_to_bmp(Path::new("data/greenblue_square-1794933754679872826.bmp")).unwrap();
+        let kernel = [0.0625, 0.25, 0.375, 0.25, 0.0625];
+        let separable = bmp.convolve_separable(&kernel, &kernel);
+
+        let (w, h) = (bmp.width as isize, bmp.height as isize);
+        for y in 0..h {
+            for x in 0..w {
+                let mut sum = [0f32; 3];
+                for (j, wy) in kernel.iter().enumerate() {
+                    for (i, wx) in kernel.iter().enumerate() {
+                        let sx = (x + i as isize - 2).clamp(0, w - 1);
+                        let sy = (y + j as isize - 2).clamp(0, h - 1);
+                        let pixel = bmp.pixels[(sy * w + sx) as usize];
+                        sum[0] += pixel.red() as f32 * wx * wy;
+                        sum[1] += pixel.green() as f32 * wx * wy;
+                        sum[2] += pixel.blue() as f32 * wx * wy;
+                    }
+                }
+                let pixel = separable.pixels[(y * w + x) as usize];
+                assert!((pixel.red() as f32 - sum[0]).abs() <= 1.0);
+                assert!((pixel.green() as f32 - sum[1]).abs() <= 1.0);
+                assert!((pixel.blue() as f32 - sum[2]).abs() <= 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_v5() {
+        let bmp =
+            Bmp::read_to_bmp(Path::new("data/greenblue_square-1794933754679872826.bmp")).unwrap();
+        let path = std::env::temp_dir().join("ode5bmp-test-v5.bmp");
+        bmp.write_v5(&path).unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        let file_header = FileHeader::from_bytes(&bytes[..14]);
+        let info_header = InfoHeader::from_bytes(&bytes[14..54]);
+        assert_eq!({ info_header.biSize }, 124);
+        assert_eq!({ file_header.bfOffBits }, 14 + 124);
+        assert_eq!(&bytes[70..74], b"BGRs");
+        assert_eq!(file_header.bfSize as usize, bytes.len());
+
+        assert_eq!(Bmp::read_to_bmp(&path).unwrap(), bmp);
+    }
+
+    #[test]
+    fn test_histogram_in_box() {
+        let mut bmp = Bmp::new(4, 4);
+        bmp.fill(
+            BoundingBox {
+                x1: 1,
+                y1: 1,
+                x2: 3,
+                y2: 3,
+            },
+            BMPixel(0x10_2030),
+        );
+        let (red, green, blue) = bmp.histogram_in_box(BoundingBox {
+            x1: 1,
+            y1: 1,
+            x2: 10,
+            y2: 3,
+        });
+        // 2x3 pixels inside the clamped box, four of them tinted
+        assert_eq!(red[0x10], 4);
+        assert_eq!(green[0x20], 4);
+        assert_eq!(blue[0x30], 4);
+        assert_eq!(red[0], 2);
+        assert_eq!(red.iter().sum::<u32>(), 6);
+    }
+
+    #[test]
+    fn test_fill_alpha() {
+        let mut bmp = Bmp::new(2, 1);
+        bmp.pixels = vec![BMPixel(0x12_3456), BMPixel(0x7f65_4321)];
+        bmp.fill_alpha(0x80);
+        assert_eq!(bmp.pixels, vec![BMPixel(0x8012_3456), BMPixel(0x8065_4321)]);
+    }
+
+    #[test]
+    fn test_equal_within() {
+        let mut bmp = Bmp::new(2, 2);
+        bmp.pixels = vec![
+            BMPixel(0x10_2030),
+            BMPixel(0xff_ffff),
+            BMPixel(0x00_0000),
+            BMPixel(0x80_8080),
+        ];
+        let mut off_by_one = bmp.clone();
+        off_by_one.pixels[0] = BMPixel(0x11_1f30);
+        off_by_one.pixels[3] = BMPixel(0x80_8081);
+
+        assert!(off_by_one.equal_within(&bmp, 1));
+        assert!(!off_by_one.equal_within(&bmp, 0));
+        assert!(!bmp.equal_within(&Bmp::new(2, 1), 255));
+    }
+
+    #[test]
+    fn test_from_grayscale_bytes() {
+        let bmp = Bmp::from_grayscale_bytes(2, 2, &[0, 128, 255, 1]).unwrap();
+        assert_eq!(
+            bmp.pixels,
+            vec![
+                BMPixel(0x00_0000),
+                BMPixel(0x80_8080),
+                BMPixel(0xff_ffff),
+                BMPixel(0x01_0101)
+            ]
+        );
+        // The first row is the visual top, which a bottom-up file stores last
+        let top_white = Bmp::from_grayscale_bytes(1, 2, &[255, 0]).unwrap();
+        assert_eq!(
+            top_white.to_bytes()[54..],
+            [0, 0, 0, 0, 0xff, 0xff, 0xff, 0]
+        );
+        assert!(matches!(
+            Bmp::from_grayscale_bytes(2, 2, &[0; 3]),
+            Err(BmpError::InvalidLength {
+                expected: 4,
+                found: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn test_apply_colormap() {
+        let ramp: Vec<u8> = vec![0, 64, 128, 192, 255];
+        let mut bmp = Bmp::from_grayscale_bytes(5, 1, &ramp).unwrap();
+        bmp.apply_colormap(&crate::colormap::JET);
+        let expected: Vec<BMPixel> = ramp
+            .iter()
+            .map(|&gray| crate::colormap::JET[gray as usize])
+            .collect();
+        assert_eq!(bmp.pixels, expected);
+        assert_eq!(bmp.pixels[0], BMPixel(0x00_007f));
+        assert_eq!(bmp.pixels[4], BMPixel(0x7f_0000));
+
+        let mut bmp = Bmp::from_grayscale_bytes(3, 1, &[0, 85, 255]).unwrap();
+        bmp.apply_colormap(&crate::colormap::HOT);
+        assert_eq!(
+            bmp.pixels,
+            vec![BMPixel(0x00_0000), BMPixel(0xff_0000), BMPixel(0xff_ffff)]
+        );
+    }
+
+    #[test]
+    fn test_rotate_hue_region() {
+        let mut bmp = Bmp::new(4, 1);
+        bmp.pixels = vec![BMPixel(0xff_0000); 4];
+        bmp.pixels[3] = BMPixel(0x80_8080);
+        bmp.rotate_hue_region(
+            BoundingBox {
+                x1: 1,
+                y1: 0,
+                x2: 10,
+                y2: 1,
+            },
+            120.0,
+        );
+        assert_eq!(
+            bmp.pixels,
+            vec![
+                BMPixel(0xff_0000),
+                BMPixel(0x00_ff00),
+                BMPixel(0x00_ff00),
+                BMPixel(0x80_8080)
+            ]
+        );
+
+        let mut bmp = Bmp::read_to_bmp(Path::new("data/france-7921693104947760092.bmp")).unwrap();
+        let original = bmp.clone();
+        bmp.rotate_hue_region(
+            BoundingBox {
+                x1: 0,
+                y1: 0,
+                x2: 30,
+                y2: 20,
+            },
+            360.0,
+        );
+        assert!(bmp.equal_within(&original, 1));
+    }
+
+    #[test]
+    fn test_packed_rows() {
+        let bmp = Bmp::read_to_bmp(Path::new("data/handcrafted-2044735835957623026.bmp")).unwrap();
+        let rows: Vec<Vec<u8>> = bmp.packed_rows().collect();
+        assert_eq!(rows.len(), 5);
+        assert!(rows.iter().all(|row| row.len() == 16));
+        assert_eq!(rows.concat(), Ode5Bmp::new(&bmp).to_bytes()[54..]);
+    }
+
+    #[test]
+    fn test_view() {
+        let mut bmp = Bmp::new(5, 4);
+        bmp.set_pixel(2, 1, BMPixel(0xab_cdef));
+        bmp.set_pixel(4, 3, BMPixel(0x12_3456));
+        let view = bmp.view(BoundingBox {
+            x1: 2,
+            y1: 1,
+            x2: 8,
+            y2: 4,
+        });
+        assert_eq!((view.width(), view.height()), (3, 3));
+        assert_eq!(view.get_pixel(0, 0), Some(BMPixel(0xab_cdef)));
+        assert_eq!(view.get_pixel(2, 2), Some(BMPixel(0x12_3456)));
+        assert_eq!(view.get_pixel(3, 0), None);
+    }
+
+    #[test]
+    fn test_composite() {
+        let base =
+            Bmp::read_to_bmp(Path::new("data/greenblue_square-1794933754679872826.bmp")).unwrap();
+
+        let mut white = Bmp::new(12, 12);
+        white.pixels = vec![BMPixel(0xff_ffff); 144];
+        let mut multiplied = base.clone();
+        multiplied.composite(&white, 0, 0, BlendMode::Multiply);
+        assert_eq!(multiplied, base);
+
+        let mut screened = base.clone();
+        screened.composite(&Bmp::new(12, 12), 0, 0, BlendMode::Screen);
+        assert_eq!(screened, base);
+
+        // Overlay darkens the darks and keeps the lights, clipped to the base
+        let mut bmp = Bmp::new(3, 1);
+        bmp.pixels = vec![BMPixel(0x40_c080); 3];
+        let mut layer = Bmp::new(4, 2);
+        layer.pixels = vec![BMPixel(0x40_4040); 8];
+        bmp.composite(&layer, 1, 0, BlendMode::Overlay);
+        assert_eq!(
+            bmp.pixels,
+            vec![BMPixel(0x40_c080), BMPixel(0x20_a141), BMPixel(0x20_a141)]
+        );
+    }
+
+    #[rstest]
+    #[case(1, 3, 2, [0, 1, 2, 3, 4, 5])]
+    #[case(2, 3, 2, [2, 1, 0, 5, 4, 3])]
+    #[case(3, 3, 2, [5, 4, 3, 2, 1, 0])]
+    #[case(4, 3, 2, [3, 4, 5, 0, 1, 2])]
+    #[case(5, 2, 3, [0, 3, 1, 4, 2, 5])]
+    #[case(6, 2, 3, [3, 0, 4, 1, 5, 2])]
+    #[case(7, 2, 3, [5, 2, 4, 1, 3, 0])]
+    #[case(8, 2, 3, [2, 5, 1, 4, 0, 3])]
+    fn test_auto_orient(
+        #[case] exif: u16,
+        #[case] width: usize,
+        #[case] height: usize,
+        #[case] expected: [u32; 6],
+    ) {
+        let mut bmp = Bmp::new(3, 2);
+        bmp.pixels = (0..6).map(BMPixel).collect();
+        bmp.auto_orient(Orientation::from_exif(exif).unwrap());
+        assert_eq!((bmp.width, bmp.height), (width, height));
+        assert_eq!(bmp.pixels, expected.map(BMPixel));
+    }
+
+    #[test]
+    fn test_dhash() {
+        let mut bmp = Bmp::new(36, 32);
+        for y in 0..32 {
+            for x in 0..36 {
+                let value = ((x * 7 + y * 3) % 64 * 3) as u32;
+                bmp.set_pixel(x, y, BMPixel(value * 0x01_0101));
+            }
+        }
+        let mut brightened = bmp.clone();
+        for pixel in brightened.pixels.iter_mut() {
+            *pixel = BMPixel(pixel.0 + 0x0a_0a0a);
+        }
+
+        let hash = bmp.dhash();
+        assert_ne!(hash, 0);
+        assert!(hamming_distance(hash, brightened.dhash()) <= 2);
+
+        let mut inverted = bmp.clone();
+        for pixel in inverted.pixels.iter_mut() {
+            *pixel = BMPixel(0xff_ffff - pixel.0);
+        }
+        assert!(hamming_distance(hash, inverted.dhash()) > 32);
+    }
+
+    #[rstest]
+    #[case(0, 0, 0)]
+    #[case(0b1011, 0b0001, 2)]
+    #[case(0, u64::MAX, 64)]
+    #[case(0x8000_0000_0000_0001, 0x0000_0000_0000_0001, 1)]
+    fn test_hamming_distance(#[case] a: u64, #[case] b: u64, #[case] expected: u32) {
+        assert_eq!(hamming_distance(a, b), expected);
+    }
+
+    #[test]
+    fn test_supersample_downscale() {
+        let mut checkerboard = Bmp::new(16, 16);
+        for y in 0..16 {
+            for x in 0..16 {
+                if (x + y) % 2 == 0 {
+                    checkerboard.set_pixel(x, y, BMPixel(0xff_ffff));
+                }
+            }
+        }
+        let small = checkerboard.supersample_downscale(5, 5);
+        assert_eq!((small.width, small.height), (5, 5));
+        for pixel in small.pixels {
+            assert!((112..=144).contains(&pixel.red()), "{pixel:?}");
+            assert_eq!(pixel.red(), pixel.green());
+        }
+
+        // Integer factors are plain box averages
+        let mut bmp = Bmp::new(2, 1);
+        bmp.pixels = vec![BMPixel(0x00_0010), BMPixel(0x00_0030)];
+        assert_eq!(
+            bmp.supersample_downscale(1, 1).pixels,
+            vec![BMPixel(0x00_0020)]
+        );
+    }
+
+    #[test]
+    fn test_draw_grid() {
+        let mut bmp = Bmp::new(7, 5);
+        bmp.draw_grid(3, 2, BMPixel(0xff_ffff));
+        for y in 0..5 {
+            for x in 0..7 {
+                let on_line = x % 3 == 0 || y % 2 == 0;
+                let expected = if on_line { 0xff_ffff } else { 0 };
+                assert_eq!(bmp.pixels[y * 7 + x], BMPixel(expected), "({x}, {y})");
+            }
+        }
+    }
+
+    #[test]
+    fn test_most_common_color() {
+        assert_eq!(Bmp::new(0, 0).most_common_color(), None);
+
+        let mut bmp = Bmp::new(6, 6);
+        bmp.pixels = vec![BMPixel(0x33_6699); 36];
+        for (i, noise) in [0x01_0203, 0xff_0000, 0x00_ff00, 0x01_0203]
+            .iter()
+            .enumerate()
+        {
+            bmp.pixels[i * 7] = BMPixel(*noise);
+        }
+        assert_eq!(bmp.most_common_color(), Some(BMPixel(0x33_6699)));
+    }
+
+    #[test]
+    fn test_replace_region() {
+        let mut bmp = Bmp::new(4, 4);
+        let mut src = Bmp::new(2, 3);
+        src.pixels = (1..=6).map(BMPixel).collect();
+        let bounds = BoundingBox {
+            x1: 1,
+            y1: 1,
+            x2: 3,
+            y2: 4,
+        };
+
+        assert!(matches!(
+            bmp.replace_region(bounds, &Bmp::new(3, 3)),
+            Err(BmpError::DimensionMismatch { .. })
+        ));
+        assert!(matches!(
+            bmp.replace_region(BoundingBox { x2: 5, ..bounds }, &src),
+            Err(BmpError::InvalidBounds)
+        ));
+        assert_eq!(bmp, Bmp::new(4, 4));
+
+        bmp.replace_region(bounds, &src).unwrap();
+        #[rustfmt::skip]
+        let expected = [
+            0, 0, 0, 0,
+            0, 1, 2, 0,
+            0, 3, 4, 0,
+            0, 5, 6, 0,
+        ];
+        assert_eq!(bmp.pixels, expected.map(BMPixel));
+    }
+
+    #[test]
+    fn test_encode_into() {
+        let first =
+            Bmp::read_to_bmp(Path::new("data/handcrafted-2044735835957623026.bmp")).unwrap();
+        let second =
+            Bmp::read_to_bmp(Path::new("data/greenblue_square-1794933754679872826.bmp")).unwrap();
+        let mut buf = Vec::new();
+
+        second.encode_into(&mut buf).unwrap();
+        assert_eq!(buf, Ode5Bmp::new(&second).to_bytes());
+        let capacity = buf.capacity();
+
+        first.encode_into(&mut buf).unwrap();
+        assert_eq!(buf, Ode5Bmp::new(&first).to_bytes());
+        assert_eq!(buf.capacity(), capacity);
+    }
+
+    #[test]
+    fn test_from_ascii() {
+        let palette = HashMap::from([
+            ('.', BMPixel::EMPTY),
+            ('r', BMPixel(0xff_0000)),
+            ('g', BMPixel(0x00_ff00)),
+        ]);
+        let bmp = Bmp::from_ascii(&["r.g", ".r.", "g.r"], &palette).unwrap();
+        assert_eq!((bmp.width, bmp.height), (3, 3));
+        #[rustfmt::skip]
+        let expected = [
+            0xff_0000, 0, 0x00_ff00,
+            0, 0xff_0000, 0,
+            0x00_ff00, 0, 0xff_0000,
+        ];
+        assert_eq!(bmp.pixels, expected.map(BMPixel));
+
+        assert!(matches!(
+            Bmp::from_ascii(&["r.g", ".r"], &palette),
+            Err(BmpError::InvalidLength {
+                expected: 3,
+                found: 2
+            })
+        ));
+        assert!(matches!(
+            Bmp::from_ascii(&["r.x"], &palette),
+            Err(BmpError::UnknownSymbol('x'))
+        ));
+    }
+
+    #[test]
+    fn test_to_ascii() {
+        let palette = HashMap::from([('.', BMPixel::EMPTY), ('#', BMPixel(0xff_ffff))]);
+        let inverse: HashMap<BMPixel, char> = palette.iter().map(|(&c, &p)| (p, c)).collect();
+        let rows = ["#..#", ".##.", "#..#"];
+
+        let mut bmp = Bmp::from_ascii(&rows, &palette).unwrap();
+        assert_eq!(bmp.to_ascii(&inverse, '?'), rows);
+
+        bmp.set_pixel(1, 1, BMPixel(0x12_3456));
+        assert_eq!(bmp.to_ascii(&inverse, '?'), ["#..#", ".?#.", "#..#"]);
+    }
+
+    #[test]
+    fn test_shift_channels() {
+        let mut bmp = Bmp::new(3, 1);
+        bmp.pixels = vec![BMPixel(0xff_8040), BMPixel(0x00_0000), BMPixel(0x00_0000)];
+        bmp.shift_channels((1, 0), (0, 0), (0, 0), BMPixel(0x11_2233));
+        assert_eq!(
+            bmp.pixels,
+            vec![BMPixel(0x11_8040), BMPixel(0xff_0000), BMPixel(0x00_0000)]
+        );
+    }
+
+    #[test]
+    fn test_is_opaque() {
+        let mut bmp = Bmp::new(3, 3);
+        bmp.fill_alpha(0xff);
+        assert!(bmp.is_opaque());
+        bmp.set_pixel(1, 2, BMPixel(0xfe12_3456));
+        assert!(!bmp.is_opaque());
+
+        // 24-bit files decode with an alpha of 0 everywhere, which means no alpha
+        let bmp = Bmp::read_to_bmp(Path::new("data/france-7921693104947760092.bmp")).unwrap();
+        assert!(bmp.is_opaque());
+    }
+
+    #[test]
+    fn test_write_auto() {
+        let path = std::env::temp_dir().join("ode5bmp-test-auto.bmp");
+        let bit_count = |bmp: &Bmp| {
+            bmp.write_auto(&path).unwrap();
+            let bytes = std::fs::read(&path).unwrap();
+            assert_eq!(
+                u32::from_le_bytes(bytes[2..6].try_into().unwrap()) as usize,
+                bytes.len()
+            );
+            u16::from_le_bytes([bytes[28], bytes[29]])
+        };
+
+        let mut bmp = Bmp::new(10, 3);
+        bmp.fill(
+            BoundingBox {
+                x1: 0,
+                y1: 0,
+                x2: 5,
+                y2: 3,
+            },
+            BMPixel(0xff_ffff),
+        );
+        assert_eq!(bit_count(&bmp), 1);
+        let bytes = std::fs::read(&path).unwrap();
+        // Black and white palette, then 0b11111000 0b00000000 padded to 4 bytes per row
+        assert_eq!(&bytes[54..62], &[0, 0, 0, 0, 0xff, 0xff, 0xff, 0]);
+        assert_eq!(&bytes[62..66], &[0b1111_1000, 0b0000_0000, 0, 0]);
+
+        bmp.set_pixel(9, 2, BMPixel(0x80_8080));
+        assert_eq!(bit_count(&bmp), 8);
+
+        let gradient = Bmp::from_grayscale_bytes(16, 16, &(0..=255).collect::<Vec<u8>>()).unwrap();
+        let mut colorful = Bmp::new(257, 1);
+        colorful.pixels = (0..257).map(|i| BMPixel(i * 0x0101)).collect();
+        assert_eq!(bit_count(&gradient), 8);
+        assert_eq!(bit_count(&colorful), 24);
+
+        bmp.fill_alpha(0xff);
+        assert_eq!(bit_count(&bmp), 8);
+        bmp.set_pixel(0, 0, BMPixel(0x80ff_ffff));
+        assert_eq!(bit_count(&bmp), 32);
+    }
+
+    #[test]
+    fn test_write_auto_round_trip() {
+        let path = std::env::temp_dir().join("ode5bmp-test-auto-round-trip.bmp");
+        let round_trip = |bmp: &Bmp, bits: u16| {
+            bmp.write_auto(&path).unwrap();
+            let bytes = std::fs::read(&path).unwrap();
+            assert_eq!(u16::from_le_bytes([bytes[28], bytes[29]]), bits);
+            let read = Bmp::read_to_bmp(&path).unwrap();
+            assert_eq!(&read, bmp, "{bits}-bit");
+            assert_eq!(Bmp::read_to_bmp_with_progress(&path, |_| {}).unwrap(), read);
+        };
+
+        let mut two_colors = Bmp::new(13, 3);
+        two_colors.set_pixel(12, 1, BMPixel(0xff_ffff));
+        round_trip(&two_colors, 1);
+
+        let gradient = Bmp::from_grayscale_bytes(16, 16, &(0..=255).collect::<Vec<u8>>()).unwrap();
+        round_trip(&gradient, 8);
+
+        let mut colorful = Bmp::new(257, 1);
+        colorful.pixels = (0..257).map(|i| BMPixel(i * 0x0101)).collect();
+        round_trip(&colorful, 24);
+
+        let mut translucent = Bmp::new(5, 2);
+        translucent.fill_alpha(0xff);
+        translucent.set_pixel(3, 1, BMPixel(0x80_12_34_56));
+        round_trip(&translucent, 32);
+        // Masks other than plain BGRA are not decoded
+        let mut swapped = std::fs::read(&path).unwrap();
+        swapped[54..58].copy_from_slice(&0x0000_00ffu32.to_le_bytes());
+        assert!(matches!(
+            Bmp::from_bytes(&swapped),
+            Err(BmpError::UnsupportedCompression(3))
+        ));
+        std::fs::remove_file(&path).unwrap();
+
+        // write_auto never picks 4-bit, but the reader handles it like the other indexed depths
+        let mut sixteen = Bmp::new(7, 2);
+        sixteen.pixels = (0..14).map(|i| BMPixel(i % 16 * 0x11_1111)).collect();
+        let palette = sixteen.distinct_colors(16);
+        let bytes = Ode5Bmp::new_indexed(&sixteen, &palette, 4).to_bytes();
+        assert_eq!(Bmp::from_bytes(&bytes).unwrap(), sixteen);
+    }
+
+    #[test]
+    fn test_rotate_180_in_place() {
+        let palette = HashMap::from([
+            ('.', BMPixel::EMPTY),
+            ('r', BMPixel(0xff_0000)),
+            ('g', BMPixel(0x00_ff00)),
+            ('b', BMPixel(0x00_00ff)),
+        ]);
+        let inverse: HashMap<BMPixel, char> = palette.iter().map(|(&c, &p)| (p, c)).collect();
+        let mut bmp = Bmp::from_ascii(&["rg..", "...b", "g..."], &palette).unwrap();
+
+        bmp.rotate_180_in_place();
+        assert_eq!(bmp.to_ascii(&inverse, '?'), ["...g", "b...", "..gr"]);
+    }
+
+    #[test]
+    fn test_add_margin() {
+        let mut bmp = Bmp::new(2, 2);
+        bmp.pixels = (1..=4).map(BMPixel).collect();
+
+        let shifted = bmp.add_margin(1, 0, 0, 0, BMPixel(9));
+        assert_eq!((shifted.width, shifted.height), (3, 2));
+        assert_eq!(shifted.pixels, [9, 1, 2, 9, 3, 4].map(BMPixel));
+
+        let framed = bmp.add_margin(0, 2, 1, 1, BMPixel(9));
+        assert_eq!((framed.width, framed.height), (4, 4));
+        #[rustfmt::skip]
+        let expected = [
+            9, 9, 9, 9,
+            1, 2, 9, 9,
+            3, 4, 9, 9,
+            9, 9, 9, 9,
+        ];
+        assert_eq!(framed.pixels, expected.map(BMPixel));
+    }
+
+    #[test]
+    fn test_channel_statistics() {
+        let mut bmp = Bmp::new(4, 1);
+        bmp.pixels = vec![
+            BMPixel(0x00_1000),
+            BMPixel(0x64_1000),
+            BMPixel(0x00_1000),
+            BMPixel(0x64_1000),
+        ];
+        let stats = bmp.channel_statistics();
+        assert_eq!((stats.red.min, stats.red.max), (0, 100));
+        assert!((stats.red.mean - 50.0).abs() < 1e-9);
+        assert!((stats.red.std_dev - 50.0).abs() < 1e-9);
+        assert_eq!(
+            stats.green,
+            ChannelSummary {
+                min: 16,
+                max: 16,
+                mean: 16.0,
+                std_dev: 0.0
+            }
+        );
+        assert_eq!(Bmp::new(0, 0).channel_statistics(), ChannelStats::default());
+    }
+
+    #[test]
+    fn test_to_web_safe() {
+        let mut bmp = Bmp::new(3, 1);
+        bmp.pixels = vec![BMPixel(0x40_5000), BMPixel(0xff_e619), BMPixel(0x1a_99cc)];
+        bmp.to_web_safe();
+        assert_eq!(
+            bmp.pixels,
+            vec![BMPixel(0x33_6600), BMPixel(0xff_ff00), BMPixel(0x33_99cc)]
+        );
+    }
+
+    #[test]
+    fn test_find_color() {
+        let mut bmp = Bmp::new(5, 4);
+        bmp.set_pixel(3, 0, BMPixel(0xff_00ff));
+        bmp.set_pixel(1, 3, BMPixel(0xff_00ff));
+        bmp.set_pixel(2, 2, BMPixel(0xff_00fe));
+        assert_eq!(bmp.find_color(BMPixel(0xff_00ff)), vec![(3, 0), (1, 3)]);
+        assert!(bmp.find_color(BMPixel(0x12_3456)).is_empty());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_convolve_separable_parallel() {
+        let mut bmp = Bmp::new(97, 61);
+        for (i, pixel) in bmp.pixels.iter_mut().enumerate() {
+            *pixel = BMPixel((i as u32).wrapping_mul(2_654_435_761) & 0x00ff_ffff);
+        }
+        let kernel = [0.0625, 0.25, 0.375, 0.25, 0.0625];
+        let sharpen = [-0.5, 2.0, -0.5];
+        assert_eq!(
+            bmp.convolve_separable_parallel(&kernel, &sharpen),
+            bmp.convolve_separable(&kernel, &sharpen)
+        );
+    }
+
+    #[test]
+    fn test_clamp_to_palette() {
+        let palette = [BMPixel(0x00_0000), BMPixel(0xff_0000), BMPixel(0xff_ffff)];
+        let mut bmp = Bmp::new(4, 1);
+        bmp.pixels = vec![
+            BMPixel(0x20_1010),
+            BMPixel(0xc0_2020),
+            BMPixel(0xe0_d0f0),
+            BMPixel(0x90_3030),
+        ];
+        bmp.clamp_to_palette(&palette);
+        assert_eq!(
+            bmp.pixels,
+            vec![
+                BMPixel(0x00_0000),
+                BMPixel(0xff_0000),
+                BMPixel(0xff_ffff),
+                BMPixel(0xff_0000)
+            ]
+        );
+        assert_eq!(BMPixel(0x12_3456).nearest_color(&[]), None);
+    }
+
+    #[rstest]
+    #[case(0, [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15], [0, 1, 2, 3, 4, 5, 6, 7, 8])]
+    #[case(1, [12, 8, 4, 0, 13, 9, 5, 1, 14, 10, 6, 2, 15, 11, 7, 3], [6, 3, 0, 7, 4, 1, 8, 5, 2])]
+    #[case(2, [15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0], [8, 7, 6, 5, 4, 3, 2, 1, 0])]
+    #[case(3, [3, 7, 11, 15, 2, 6, 10, 14, 1, 5, 9, 13, 0, 4, 8, 12], [2, 5, 8, 1, 4, 7, 0, 3, 6])]
+    fn test_try_rotate_in_place(
+        #[case] quarter_turns: u8,
+        #[case] even: [u32; 16],
+        #[case] odd: [u32; 9],
+    ) {
+        // Quarter turns are clockwise, so one turn brings the left column to the top row
+        let mut bmp = Bmp::new(4, 4);
+        bmp.pixels = (0..16).map(BMPixel).collect();
+        bmp.try_rotate_in_place(quarter_turns).unwrap();
+        assert_eq!(bmp.pixels, even.map(BMPixel));
+
+        let mut bmp = Bmp::new(3, 3);
+        bmp.pixels = (0..9).map(BMPixel).collect();
+        bmp.try_rotate_in_place(quarter_turns + 4).unwrap();
+        assert_eq!(bmp.pixels, odd.map(BMPixel));
+
+        assert!(matches!(
+            Bmp::new(4, 3).try_rotate_in_place(quarter_turns),
+            Err(BmpError::NotSquare {
+                width: 4,
+                height: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn test_flatten() {
+        let mut bmp = Bmp::new(3, 1);
+        bmp.pixels = vec![
+            BMPixel(0x80ff_ffff),
+            BMPixel(0xff12_3456),
+            BMPixel(0x00ff_ffff),
+        ];
+        let flat = bmp.flatten(BMPixel(0x00_0000));
+        assert_eq!(
+            flat.pixels,
+            vec![
+                BMPixel(0xff80_8080),
+                BMPixel(0xff12_3456),
+                BMPixel(0xff00_0000)
+            ]
+        );
+        assert!(flat.is_opaque());
+
+        // A 24-bit file has no alpha, so it keeps its colors rather than vanishing
+        let bmp = Bmp::read_to_bmp(Path::new("data/france-7921693104947760092.bmp")).unwrap();
+        let mut expected = bmp.clone();
+        expected.fill_alpha(0xff);
+        assert_eq!(bmp.flatten(BMPixel(0x00_0000)), expected);
+    }
+
+    #[test]
+    fn test_fill_noise() {
+        let bounds = BoundingBox {
+            x1: 1,
+            y1: 1,
+            x2: 20,
+            y2: 3,
+        };
+        let mut first = Bmp::new(8, 4);
+        let mut second = Bmp::new(8, 4);
+        let mut third = Bmp::new(8, 4);
+        first.fill_noise(bounds, 42);
+        second.fill_noise(bounds, 42);
+        third.fill_noise(bounds, 43);
+
+        assert_eq!(first, second);
+        assert_ne!(first, third);
+        assert_eq!(first.pixels[0], BMPixel::EMPTY);
+        assert_eq!(first.pixels[3 * 8 + 4], BMPixel::EMPTY);
+        assert!(first.pixels[8 + 1..8 + 8]
+            .iter()
+            .all(|&p| p != BMPixel::EMPTY));
+    }
+
+    #[test]
+    fn test_draw_dashed_line() {
+        let mut bmp = Bmp::new(12, 2);
+        bmp.draw_dashed_line(0, 0, 20, 0, BMPixel(1), 3, 2);
+        let row: String = bmp.pixels[..12]
+            .iter()
+            .map(|&pixel| if pixel == BMPixel(1) { '#' } else { '.' })
+            .collect();
+        assert_eq!(row, "###..###..##");
+        assert!(bmp.pixels[12..]
+            .iter()
+            .all(|&pixel| pixel == BMPixel::EMPTY));
+    }
+
+    #[test]
+    fn test_median_cut_palette() {
+        let mut bmp = Bmp::new(4, 4);
+        bmp.fill(
+            BoundingBox {
+                x1: 0,
+                y1: 0,
+                x2: 1,
+                y2: 4,
+            },
+            BMPixel(0x20_40c0),
+        );
+        assert_eq!(
+            bmp.median_cut_palette(16),
+            vec![BMPixel(0x00_0000), BMPixel(0x20_40c0)]
+        );
+        assert_eq!(bmp.median_cut_palette(1), vec![BMPixel(0x08_1030)]);
+
+        let gradient = Bmp::from_grayscale_bytes(16, 16, &(0..=255).collect::<Vec<u8>>()).unwrap();
+        let palette = gradient.median_cut_palette(4);
+        assert_eq!(
+            palette,
+            [0x20_2020, 0x60_6060, 0xa0_a0a0, 0xe0_e0e0].map(BMPixel)
+        );
+
+        // A color that fills most of the image stays whole instead of bleeding into the other
+        let (a, b) = (BMPixel(0x10_2030), BMPixel(0xc0_b0a0));
+        let mut skewed = Bmp::new(4, 1);
+        skewed.pixels = vec![a, a, b, a];
+        assert_eq!(skewed.median_cut_palette(2), vec![a, b]);
+    }
+
+    #[test]
+    fn test_row_run_lengths() {
+        let palette = HashMap::from([
+            ('A', BMPixel(0xaa_0000)),
+            ('B', BMPixel(0x00_bb00)),
+            ('C', BMPixel(0x00_00cc)),
+        ]);
+        let bmp = Bmp::from_ascii(&["CCCCCC", "AABBBC"], &palette).unwrap();
+        assert_eq!(
+            bmp.row_run_lengths(1),
+            Some(vec![
+                (BMPixel(0xaa_0000), 2),
+                (BMPixel(0x00_bb00), 3),
+                (BMPixel(0x00_00cc), 1)
+            ])
+        );
+        assert_eq!(bmp.row_run_lengths(0), Some(vec![(BMPixel(0x00_00cc), 6)]));
+        assert_eq!(bmp.row_run_lengths(2), None);
+    }
+
+    #[test]
+    fn test_draw_text_centered() {
+        let mut bmp = Bmp::new(11, 7);
+        bmp.draw_text_centered("AB", 1, BMPixel(0xff_ffff));
+        let lit_columns: Vec<usize> = (0..11)
+            .filter(|&x| (0..7).any(|y| bmp.pixels[y * 11 + x] != BMPixel::EMPTY))
+            .collect();
+        assert_eq!(lit_columns.first(), Some(&2));
+        assert_eq!(lit_columns.last(), Some(&8));
+        assert!(bmp.pixels[..11]
+            .iter()
+            .all(|&pixel| pixel == BMPixel::EMPTY));
+
+        let palette = HashMap::from([(BMPixel::EMPTY, '.'), (BMPixel(0xff_ffff), '#')]);
+        assert_eq!(
+            bmp.to_ascii(&palette, '?')[1..6],
+            [
+                "...#..##...",
+                "..#.#.#.#..",
+                "..###.##...",
+                "..#.#.#.#..",
+                "..#.#.##...",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_draw_text_orientation_on_disk() {
+        let white = BMPixel(0xff_ffff);
+        let mut bmp = Bmp::new(3, 5);
+        bmp.draw_text(0, 0, "T", white);
+        let path = std::env::temp_dir().join("ode5bmp-draw-text.bmp");
+        bmp.write_to_file(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // The file stores the bottom row first: the stem of the T, with the bar last
+        let rows: Vec<&[u8]> = bytes[54..].chunks_exact(12).map(|row| &row[..9]).collect();
+        assert_eq!(rows[0], [0, 0, 0, 0xff, 0xff, 0xff, 0, 0, 0]);
+        assert_eq!(rows[4], [0xff; 9]);
+    }
+
+    #[test]
+    fn test_measure_text() {
+        assert_eq!(font::measure_text(""), (0, 0));
+        assert_eq!(font::measure_text("A"), (3, 5));
+        assert_eq!(font::measure_text("AB\nCDEF"), (15, 11));
+
+        let mut bmp = Bmp::new(20, 20);
+        bmp.draw_text(0, 0, "AB\nCDEF", BMPixel(1));
+        let lit = bmp.find_color(BMPixel(1));
+        assert_eq!(lit.iter().map(|&(x, _)| x).max(), Some(14));
+        assert_eq!(lit.iter().map(|&(_, y)| y).max(), Some(10));
+    }
+
+    #[test]
+    fn test_draw_text_wrapped() {
+        let text = "the quick brown fox jumps";
+        assert_eq!(
+            font::wrap_text(text, 40),
+            vec!["the quick", "brown fox", "jumps"]
+        );
+        assert_eq!(font::wrap_text(text, 200), vec![text]);
+        assert_eq!(
+            font::wrap_text("extraordinary a", 10),
+            vec!["extraordinary", "a"]
+        );
+
+        let mut bmp = Bmp::new(40, 30);
+        bmp.draw_text_wrapped(0, 0, text, 40, BMPixel(1));
+        let lit = bmp.find_color(BMPixel(1));
+        assert!(lit.iter().all(|&(x, _)| x < 35));
+        assert_eq!(lit.iter().map(|&(_, y)| y).max(), Some(16));
+    }
+
+    #[test]
+    fn test_adaptive_threshold() {
+        // Dark marks on a background lit from dim (left) to bright (right)
+        let (width, height) = (32, 8);
+        let is_mark = |x: usize, y: usize| y == 4 && x % 4 == 2;
+        let mut luminance = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let background = 40 + x as u8 * 6;
+                luminance.push(if is_mark(x, y) {
+                    background - 40
+                } else {
+                    background
+                });
+            }
+        }
+        let mut bmp = Bmp::from_grayscale_bytes(width, height, &luminance).unwrap();
+
+        // A global threshold loses the marks on the bright side
+        let global: Vec<bool> = luminance.iter().map(|&l| l > 128).collect();
+        assert!(global[4 * width + 30]);
+
+        bmp.adaptive_threshold(5, -10);
+        for y in 0..height {
+            for x in 0..width {
+                let expected = if is_mark(x, y) { 0 } else { 0xff_ffff };
+                assert_eq!(bmp.pixels[y * width + x], BMPixel(expected), "({x}, {y})");
+            }
+        }
+    }
+
+    #[test]
+    fn test_integral_image() {
+        let mut bmp = Bmp::new(7, 5);
+        bmp.fill_noise(
+            BoundingBox {
+                x1: 0,
+                y1: 0,
+                x2: 7,
+                y2: 5,
+            },
+            7,
+        );
+        let table = bmp.integral_image();
+        assert_eq!(table.len(), 8 * 6);
+
+        let t = |x: usize, y: usize| table[y * 8 + x];
+        let (x1, y1, x2, y2) = (2, 1, 6, 4);
+        let mut brute_force = 0u64;
+        for y in y1..y2 {
+            for x in x1..x2 {
+                brute_force += bmp.pixels[y * 7 + x].luminance() as u64;
+            }
+        }
+        assert_eq!(t(x2, y2) + t(x1, y1) - t(x1, y2) - t(x2, y1), brute_force);
+        assert_eq!(
+            t(7, 5),
+            bmp.pixels.iter().map(|p| p.luminance() as u64).sum::<u64>()
+        );
+    }
+
+    #[test]
+    fn test_debug_dump() {
+        let mut bmp = Bmp::new(2, 2);
+        bmp.pixels = vec![
+            BMPixel(0xff_0000),
+            BMPixel(0x00_ff00),
+            BMPixel(0x00_00ff),
+            BMPixel(0x12_ab34),
+        ];
+        assert_eq!(bmp.debug_dump(), "#FF0000 #00FF00\n#0000FF #12AB34");
+    }
+
+    #[test]
+    fn test_pad_to_power_of_two() {
+        let mut bmp = Bmp::new(3, 5);
+        bmp.pixels = (0..15).map(BMPixel).collect();
+        let padded = bmp.pad_to_power_of_two(BMPixel(99));
+        assert_eq!((padded.width, padded.height), (4, 8));
+        assert_eq!(padded.pixels[..4], [0, 1, 2, 99].map(BMPixel));
+        assert_eq!(
+            padded.pixels[4 * 4..4 * 4 + 4],
+            [12, 13, 14, 99].map(BMPixel)
+        );
+        assert!(padded.pixels[5 * 4..]
+            .iter()
+            .all(|&pixel| pixel == BMPixel(99)));
+
+        let square = Bmp::new(16, 16);
+        assert_eq!(square.pad_to_power_of_two(BMPixel(99)), square);
+    }
+
+    #[test]
+    fn test_get_pixel() {
+        let mut bmp = Bmp::new(4, 3);
+        bmp.set_pixel(0, 0, BMPixel(1));
+        bmp.set_pixel(3, 2, BMPixel(2));
+
+        assert_eq!(bmp.get_pixel(0, 0), Some(BMPixel(1)));
+        assert_eq!(bmp.get_pixel(3, 2), Some(BMPixel(2)));
+        assert_eq!(bmp.get_pixel(4, 0), None);
+        assert_eq!(bmp.get_pixel(0, 3), None);
+        assert_eq!(bmp.get_pixel_unchecked(3, 2), BMPixel(2));
+    }
+
+    #[test]
+    fn test_try_set_pixel() {
+        let mut bmp = Bmp::new(4, 3);
+        assert_eq!(bmp.try_set_pixel(0, 0, BMPixel(1)), Ok(()));
+        assert_eq!(bmp.try_set_pixel(3, 2, BMPixel(2)), Ok(()));
+        assert_eq!(
+            bmp.try_set_pixel(4, 2, BMPixel(3)),
+            Err(OutOfBounds {
+                x: 4,
+                y: 2,
+                width: 4,
+                height: 3
+            })
+        );
+        assert!(bmp.try_set_pixel(3, 3, BMPixel(3)).is_err());
+        assert_eq!(bmp.get_pixel(0, 0), Some(BMPixel(1)));
+        assert_eq!(bmp.get_pixel(3, 2), Some(BMPixel(2)));
+        assert_eq!(bmp.find_color(BMPixel(3)), vec![]);
+    }
+
+    #[test]
+    fn test_render_histogram() {
+        let bmp = Bmp::from_grayscale_bytes(4, 4, &[0x60; 16]).unwrap();
+        let plot = bmp.render_histogram(256, 10);
+        assert_eq!((plot.width, plot.height), (256, 10));
+        let lit = plot.find_color(BMPixel(0xff_ffff));
+        assert_eq!(lit.len(), 10);
+        assert!(lit.iter().all(|&(x, _)| x == 0x60));
+
+        let two_tone = Bmp::from_grayscale_bytes(4, 1, &[0, 0, 0, 255]).unwrap();
+        let plot = two_tone.render_histogram(512, 9);
+        assert_eq!(plot.find_color(BMPixel(0xff_ffff)).len(), 2 * 9 + 2 * 3);
+    }
+
+    #[test]
+    fn test_crop_to_aspect() {
+        let mut bmp = Bmp::new(4, 2);
+        bmp.pixels = (0..8).map(BMPixel).collect();
+        let square = bmp.crop_to_aspect(1, 1);
+        assert_eq!((square.width, square.height), (2, 2));
+        assert_eq!(square.pixels, [1, 2, 5, 6].map(BMPixel));
+
+        let wide = Bmp::new(30, 20).crop_to_aspect(16, 9);
+        assert_eq!((wide.width, wide.height), (30, 16));
+    }
+
+    #[test]
+    fn test_pixel_alpha() {
+        let pixel = BMPixel::from_rgba(1, 2, 3, 4);
+        assert_eq!(pixel, BMPixel(0x0401_0203));
+        assert_eq!(pixel.alpha(), 4);
+        assert_eq!((pixel.red(), pixel.green(), pixel.blue()), (1, 2, 3));
+        assert_eq!(BMPixel::from_rgb(1, 2, 3).alpha(), 0xff);
+        assert_eq!(pixel.with_alpha(0x80), BMPixel(0x8001_0203));
+        assert_eq!(BMPixel(0x00_00ff).alpha(), 0);
+    }
+
+    #[test]
+    fn test_unsharp_mask() {
+        let ramp = [0, 0, 0, 64, 128, 192, 255, 255, 255];
+        let mut bmp = Bmp::from_grayscale_bytes(9, 1, &ramp).unwrap();
+        bmp.unsharp_mask(1.0, 1.0);
+
+        let sharpened: Vec<u8> = bmp.pixels.iter().map(|pixel| pixel.red()).collect();
+        assert!(sharpened[3] < 64, "{sharpened:?}");
+        assert!(sharpened[5] > 192, "{sharpened:?}");
+        let steepest = |values: &[u8]| values.windows(2).map(|w| w[0].abs_diff(w[1])).max();
+        assert!(steepest(&sharpened) > steepest(&ramp));
+        assert_eq!((sharpened[0], sharpened[8]), (0, 255));
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        let bmp =
+            Bmp::read_to_bmp(Path::new("data/greenblue_square-1794933754679872826.bmp")).unwrap();
+        let bytes = bmp.to_bytes();
+        assert_eq!(Bmp::from_bytes(&bytes).unwrap(), bmp);
+
+        assert!(matches!(
+            Bmp::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(BmpError::TruncatedPixelData)
+        ));
+        assert!(matches!(Bmp::from_bytes(b"BM"), Err(BmpError::NotBmp)));
+    }
+
+    #[test]
+    fn test_swap_red_blue() {
+        let mut bmp = Bmp::new(2, 1);
+        bmp.set_pixel(0, 0, BMPixel(0x11_2233));
+        bmp.set_pixel(1, 0, BMPixel(0x80ab_cdef));
+        bmp.swap_red_blue();
+        assert_eq!(bmp.pixels, vec![BMPixel(0x33_2211), BMPixel(0x80ef_cdab)]);
+    }
+
+    #[test]
+    fn test_to_bytes_roundtrip() {
+        let mut bmp = Bmp::new(8, 3);
+        for (i, pixel) in bmp.pixels.iter_mut().enumerate() {
+            *pixel = BMPixel(i as u32 * 0x0a_0b0c);
+        }
+        let bytes = bmp.to_bytes();
+        assert_eq!(&bytes[..2], b"BM");
+        assert_eq!(Bmp::from_bytes(&bytes).unwrap(), bmp);
+    }
+
+    #[test]
+    fn test_opaque_bounds() {
+        // A 24-bit file has no alpha, so all of it counts as opaque
+        let bmp = Bmp::read_to_bmp(Path::new("data/france-7921693104947760092.bmp")).unwrap();
+        assert_eq!(
+            bmp.opaque_bounds(),
+            Some(BoundingBox {
+                x1: 0,
+                y1: 0,
+                x2: 30,
+                y2: 20
+            })
+        );
+        assert_eq!(Bmp::new(0, 0).opaque_bounds(), None);
+
+        let mut sprite = Bmp::new(6, 5);
+        sprite.set_pixel(2, 1, BMPixel::from_rgba(255, 0, 0, 1));
+        sprite.set_pixel(4, 3, BMPixel::from_rgb(0, 0, 0));
+        sprite.set_pixel(1, 2, BMPixel::from_rgba(0, 255, 0, 128));
+        sprite.set_pixel(5, 4, BMPixel(0x00ff_ffff));
+        assert_eq!(
+            sprite.opaque_bounds(),
+            Some(BoundingBox {
+                x1: 1,
+                y1: 1,
+                x2: 5,
+                y2: 4
+            })
+        );
+    }
+
+    #[test]
+    fn test_index_by_xy() {
+        let mut bmp = Bmp::new(3, 2);
+        bmp[(2, 1)] = BMPixel(0xff_0000);
+        assert_eq!(bmp.get_pixel(2, 1), Some(BMPixel(0xff_0000)));
+        assert_eq!(bmp[(2, 1)], BMPixel(0xff_0000));
+        assert_eq!(bmp[(1, 1)], BMPixel::EMPTY);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn test_index_out_of_bounds() {
+        let bmp = Bmp::new(3, 2);
+        let _ = bmp[(3, 0)];
+    }
+
+    #[test]
+    fn test_pack_atlas() {
+        let sprite = |width, height, color: BMPixel| Bmp {
+            width,
+            height,
+            pixels: vec![color; width * height],
+        };
+        let sprites = [
+            sprite(4, 2, BMPixel(0xff_0000)),
+            sprite(3, 5, BMPixel(0x00_ff00)),
+            sprite(2, 3, BMPixel(0x00_00ff)),
+        ];
+        let (atlas, placements) = Bmp::pack_atlas(&sprites, 1);
+        assert_eq!(placements.len(), 3);
+
+        for (i, (sprite, a)) in sprites.iter().zip(&placements).enumerate() {
+            assert_eq!((a.x2 - a.x1, a.y2 - a.y1), (sprite.width, sprite.height));
+            assert!(a.x2 <= atlas.width && a.y2 <= atlas.height);
+            assert_eq!(atlas.get_pixel(a.x1, a.y1), Some(sprite.pixels[0]));
+            assert_eq!(atlas.get_pixel(a.x2 - 1, a.y2 - 1), Some(sprite.pixels[0]));
+            for b in &placements[i + 1..] {
+                let overlap = a.x1 < b.x2 && b.x1 < a.x2 && a.y1 < b.y2 && b.y1 < a.y2;
+                assert!(!overlap, "{a:?} overlaps {b:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_flip_horizontal_and_vertical() {
+        let original = Bmp {
+            width: 3,
+            height: 2,
+            pixels: (1..=6).map(BMPixel).collect(),
+        };
+
+        let mut bmp = original.clone();
+        bmp.flip_horizontal();
+        assert_eq!(bmp.pixels, [3, 2, 1, 6, 5, 4].map(BMPixel));
+
+        let mut bmp = original.clone();
+        bmp.flip_vertical();
+        assert_eq!(bmp.pixels, [4, 5, 6, 1, 2, 3].map(BMPixel));
+
+        bmp.flip_vertical();
+        assert_eq!(bmp, original);
+    }
+
+    #[test]
+    fn test_rotate90_180_270() {
+        let original = Bmp {
+            width: 2,
+            height: 3,
+            pixels: (1..=6).map(BMPixel).collect(),
+        };
+
+        let quarter = original.rotate90();
+        assert_eq!((quarter.width, quarter.height), (3, 2));
+        assert_eq!(quarter.pixels, [5, 3, 1, 6, 4, 2].map(BMPixel));
+        assert_eq!(original.rotate180().pixels, [6, 5, 4, 3, 2, 1].map(BMPixel));
+        assert_eq!(original.rotate270(), quarter.rotate180());
+
+        let full_turn = original.rotate90().rotate90().rotate90().rotate90();
+        assert_eq!(full_turn, original);
+    }
+
+    #[rstest]
+    #[case(30, 20, 16, (32, 32))]
+    #[case(32, 16, 16, (32, 16))]
+    #[case(5, 7, 1, (5, 7))]
+    fn test_resize_canvas_to_multiple(
+        #[case] width: usize,
+        #[case] height: usize,
+        #[case] multiple: usize,
+        #[case] expected: (usize, usize),
+    ) {
+        let mut bmp = Bmp::new(width, height);
+        bmp.set_pixel(width - 1, height - 1, BMPixel(0x12_3456));
+        let padded = bmp.resize_canvas_to_multiple(multiple, BMPixel(0xff_ffff));
+        assert_eq!((padded.width, padded.height), expected);
+        assert_eq!(padded.get_pixel(0, 0), Some(BMPixel::EMPTY));
+        assert_eq!(
+            padded.get_pixel(width - 1, height - 1),
+            Some(BMPixel(0x12_3456))
+        );
+        if expected.0 > width {
+            assert_eq!(padded.get_pixel(width, 0), Some(BMPixel(0xff_ffff)));
+        }
+    }
+
+    #[test]
+    fn test_fade_edge() {
+        let mut bmp = Bmp::new(3, 8);
+        bmp.fill(
+            BoundingBox {
+                x1: 0,
+                y1: 0,
+                x2: 3,
+                y2: 8,
+            },
+            BMPixel(0x12_3456),
+        );
+        bmp.fade_edge(Edge::Top, 4);
+
+        let alphas: Vec<u8> = (0..8)
+            .map(|y| bmp.get_pixel(1, y).unwrap().alpha())
+            .collect();
+        assert_eq!(alphas, [0, 63, 127, 191, 255, 255, 255, 255]);
+        assert!(bmp
+            .pixels
+            .iter()
+            .all(|pixel| pixel.0 & 0xff_ffff == 0x12_3456));
+
+        bmp.fade_edge(Edge::Right, 2);
+        assert_eq!(bmp.get_pixel(2, 5).unwrap().alpha(), 0);
+        assert_eq!(bmp.get_pixel(1, 5).unwrap().alpha(), 127);
+        assert_eq!(bmp.get_pixel(0, 5).unwrap().alpha(), 255);
+    }
+
+    #[test]
+    fn test_crop() {
+        let bmp = Bmp::read_to_bmp(Path::new("data/handcrafted-2044735835957623026.bmp")).unwrap();
+        let interior = BoundingBox {
+            x1: 1,
+            y1: 1,
+            x2: 4,
+            y2: 3,
+        };
+        let cropped = bmp.crop(interior).unwrap();
+        assert_eq!((cropped.width, cropped.height), (3, 2));
+        assert_eq!(cropped.get_pixel(0, 0), bmp.get_pixel(1, 1));
+        assert_eq!(cropped.get_pixel(2, 1), bmp.get_pixel(3, 2));
+    }
+
+    #[rstest]
+    #[case(BoundingBox { x1: 2, y1: 0, x2: 2, y2: 3 })]
+    #[case(BoundingBox { x1: 0, y1: 3, x2: 2, y2: 1 })]
+    #[case(BoundingBox { x1: 0, y1: 0, x2: 6, y2: 5 })]
+    fn test_crop_invalid_bounds(#[case] bounds: BoundingBox) {
+        let bmp = Bmp::new(5, 5);
+        assert!(matches!(bmp.crop(bounds), Err(BmpError::InvalidBounds)));
+    }
+
+    #[test]
+    fn test_color_distance_map() {
+        let mut bmp = Bmp::new(3, 1);
+        bmp.set_pixel(0, 0, BMPixel(0x40_8020));
+        bmp.set_pixel(1, 0, BMPixel(0xff_ffff));
+        bmp.set_pixel(2, 0, BMPixel(0x40_8021));
+
+        let map = Bmp::new(1, 1).color_distance_map(BMPixel(0xff_ffff));
+        assert_eq!(map.pixels, vec![BMPixel(0xff_ffff)]);
+
+        let map = bmp.color_distance_map(BMPixel(0x40_8020));
+        assert_eq!(map.pixels[0], BMPixel::EMPTY);
+        assert!(map.pixels[1].red() > map.pixels[2].red());
+        assert!(map
+            .pixels
+            .iter()
+            .all(|p| p.red() == p.green() && p.green() == p.blue()));
+    }
+
+    #[test]
+    fn test_resize_nearest() {
+        let (black, white) = (BMPixel(0x00_0000), BMPixel(0xff_ffff));
+        let checkerboard = Bmp {
+            width: 2,
+            height: 2,
+            pixels: vec![black, white, white, black],
+        };
+        let upscaled = checkerboard.resize_nearest(4, 4);
+        assert_eq!((upscaled.width, upscaled.height), (4, 4));
+        for y in 0..4 {
+            for x in 0..4 {
+                let expected = if (x / 2 + y / 2) % 2 == 0 {
+                    black
+                } else {
+                    white
+                };
+                assert_eq!(upscaled.get_pixel(x, y), Some(expected), "({x}, {y})");
+            }
+        }
+        assert_eq!(upscaled.resize_nearest(2, 2), checkerboard);
+
+        let empty = checkerboard.resize_nearest(0, 3);
+        assert_eq!((empty.width, empty.height), (0, 3));
+        assert!(empty.pixels.is_empty());
+    }
+
+    #[test]
+    fn test_apply_lut_3d() {
+        let original =
+            Bmp::read_to_bmp(Path::new("data/greenblue_square-1794933754679872826.bmp")).unwrap();
+        let mut bmp = original.clone();
+        bmp.set_pixel(0, 0, BMPixel(0x8012_3456));
+        let expected = bmp.clone();
+        bmp.apply_lut_3d(&Lut3d::identity(17));
+        assert_eq!(bmp, expected);
+
+        // A two-point cube that swaps the ends of every axis inverts the image
+        let inverted = (0..8)
+            .map(|i| [0, 1, 2].map(|axis| if i >> axis & 1 == 0 { 1.0 } else { 0.0 }))
+            .collect();
+        let invert = Lut3d::from_table(2, inverted).unwrap();
+        let mut bmp = Bmp::new(1, 1);
+        bmp.set_pixel(0, 0, BMPixel(0x20_4080));
+        bmp.apply_lut_3d(&invert);
+        assert_eq!(bmp.pixels, vec![BMPixel(0xdf_bf7f)]);
+
+        assert!(matches!(
+            Lut3d::from_table(2, vec![[0.0; 3]; 7]),
+            Err(BmpError::InvalidLength {
+                expected: 8,
+                found: 7
+            })
+        ));
+    }
+
+    #[rstest]
+    #[case(BMPixel(0xff_0000), BMPixel(0x4c_4c4c))]
+    #[case(BMPixel(0x00_ff00), BMPixel(0x95_9595))]
+    #[case(BMPixel(0xff_ffff), BMPixel(0xff_ffff))]
+    #[case(BMPixel(0x8000_00ff), BMPixel(0x801c_1c1c))]
+    fn test_to_grayscale(#[case] color: BMPixel, #[case] expected: BMPixel) {
+        let mut bmp = Bmp::new(1, 1);
+        bmp.set_pixel(0, 0, color);
+        assert_eq!(bmp.to_grayscale().pixels, vec![expected]);
+    }
+
+    #[test]
+    fn test_lut_from_cube_file() {
+        let path = std::env::temp_dir().join("ode5bmp-invert.cube");
+        let cube = "\
+# Inverts every channel
+TITLE \"invert\"
+LUT_3D_SIZE 2
+DOMAIN_MIN 0 0 0
+DOMAIN_MAX 1 1 1
+
+1.0 1.0 1.0
+0.0 1.0 1.0
+1.0 0.0 1.0
+0.0 0.0 1.0
+1.0 1.0 0.0
+0.0 1.0 0.0
+1.0 0.0 0.0
+0.0 0.0 0.0
+";
+        std::fs::write(&path, cube).unwrap();
+        let lut = Lut3d::from_cube_file(&path).unwrap();
+        assert_eq!(lut.size(), 2);
+
+        let mut bmp = Bmp::new(1, 1);
+        bmp.set_pixel(0, 0, BMPixel(0x20_4080));
+        bmp.apply_lut_3d(&lut);
+        assert_eq!(bmp.pixels, vec![BMPixel(0xdf_bf7f)]);
+
+        std::fs::write(&path, "LUT_3D_SIZE 2\n0.0 0.0\n").unwrap();
+        assert!(matches!(
+            Lut3d::from_cube_file(&path),
+            Err(BmpError::InvalidCube { line: 2 })
+        ));
+        std::fs::write(&path, "0.0 0.0 0.0\n").unwrap();
+        assert!(matches!(
+            Lut3d::from_cube_file(&path),
+            Err(BmpError::InvalidCube { line: 1 })
+        ));
+        std::fs::write(&path, "LUT_3D_SIZE 2\n0.0 0.0 0.0\n").unwrap();
+        assert!(matches!(
+            Lut3d::from_cube_file(&path),
+            Err(BmpError::InvalidLength {
+                expected: 8,
+                found: 1
+            })
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_invert() {
+        let original =
+            Bmp::read_to_bmp(Path::new("data/greenblue_square-1794933754679872826.bmp")).unwrap();
+        let mut bmp = original.clone();
+        bmp.set_pixel(0, 0, BMPixel(0x8012_3456));
+        bmp.set_pixel(1, 0, BMPixel(0x00_0000));
+
+        bmp.invert();
+        assert_eq!(bmp.get_pixel(0, 0), Some(BMPixel(0x80ed_cba9)));
+        assert_eq!(bmp.get_pixel(1, 0), Some(BMPixel(0xff_ffff)));
+
+        bmp.invert();
+        bmp.set_pixel(0, 0, original.pixels[0]);
+        bmp.set_pixel(1, 0, original.pixels[1]);
+        assert_eq!(bmp, original);
+    }
+
+    #[test]
+    fn test_apply_per_row() {
+        let mut bmp = Bmp::from_grayscale_bytes(2, 4, &[200; 8]).unwrap();
+        bmp.apply_per_row(|y, row| {
+            if y % 2 == 1 {
+                for pixel in row.iter_mut() {
+                    *pixel = pixel.scaled(0.5);
+                }
+            }
+        });
+        let rows: Vec<u8> = (0..4).map(|y| bmp.get_pixel(1, y).unwrap().red()).collect();
+        assert_eq!(rows, [200, 100, 200, 100]);
+    }
+
+    #[rstest]
+    #[case((1, 2, 4, 2), &[(1, 2), (2, 2), (3, 2), (4, 2)])]
+    #[case((3, 4, 3, 1), &[(3, 1), (3, 2), (3, 3), (3, 4)])]
+    #[case((0, 0, 3, 3), &[(0, 0), (1, 1), (2, 2), (3, 3)])]
+    #[case((3, 3, 7, 7), &[(3, 3), (4, 4)])]
+    fn test_draw_line(
+        #[case] line: (usize, usize, usize, usize),
+        #[case] expected: &[(usize, usize)],
+    ) {
+        let color = BMPixel(0xff_0000);
+        let mut bmp = Bmp::new(5, 5);
+        let (x0, y0, x1, y1) = line;
+        bmp.draw_line(x0, y0, x1, y1, color);
+        for y in 0..5 {
+            for x in 0..5 {
+                let drawn = bmp.get_pixel(x, y) == Some(color);
+                assert_eq!(drawn, expected.contains(&(x, y)), "({x}, {y})");
+            }
+        }
+    }
+
+    #[test]
+    fn test_crt_scanlines() {
+        let mut bmp = Bmp::from_grayscale_bytes(2, 6, &[200; 12]).unwrap();
+        bmp.crt_scanlines(0.25, 3);
+        let rows: Vec<u8> = (0..6).map(|y| bmp.get_pixel(0, y).unwrap().red()).collect();
+        assert_eq!(rows, [200, 200, 150, 200, 200, 150]);
+
+        bmp.crt_scanlines(2.0, 2);
+        let rows: Vec<u8> = (0..6).map(|y| bmp.get_pixel(1, y).unwrap().red()).collect();
+        assert_eq!(rows, [200, 0, 150, 0, 200, 0]);
+    }
+
+    #[test]
+    fn test_draw_rect() {
+        let color = BMPixel(0x00_ff00);
+        let mut bmp = Bmp::new(5, 5);
+        bmp.draw_rect(
+            BoundingBox {
+                x1: 0,
+                y1: 0,
+                x2: 5,
+                y2: 5,
+            },
+            color,
+        );
+        for y in 0..5 {
+            for x in 0..5 {
+                let border = x == 0 || y == 0 || x == 4 || y == 4;
+                assert_eq!(bmp.get_pixel(x, y) == Some(color), border, "({x}, {y})");
+            }
+        }
+
+        let mut bmp = Bmp::new(5, 5);
+        bmp.draw_rect(
+            BoundingBox {
+                x1: 3,
+                y1: 2,
+                x2: 9,
+                y2: 4,
+            },
+            color,
+        );
+        let drawn = bmp.pixels.iter().filter(|&&pixel| pixel == color).count();
+        assert_eq!(drawn, 4);
+        assert_eq!(bmp.get_pixel(3, 2), Some(color));
+        assert_eq!(bmp.get_pixel(4, 3), Some(color));
+    }
+
+    #[rstest]
+    #[case(|x: usize, y: usize| x + y, 45.0)]
+    #[case(|x: usize, y: usize| x + 20 - y, -45.0)]
+    #[case(|x: usize, _y: usize| x, 0.0)]
+    #[case(|_x: usize, y: usize| y, 90.0)]
+    fn test_dominant_gradient_direction(
+        #[case] phase: fn(usize, usize) -> usize,
+        #[case] expected: f32,
+    ) {
+        let size = 20;
+        let stripes: Vec<u8> = (0..size * size)
+            .map(|i| {
+                let t = phase(i % size, i / size) as f32 * 0.6;
+                (128.0 + 100.0 * t.sin()) as u8
+            })
+            .collect();
+        let bmp = Bmp::from_grayscale_bytes(size, size, &stripes).unwrap();
+        let direction = bmp.dominant_gradient_direction();
+        assert!((direction - expected).abs() < 2.0, "{direction}");
+    }
+
+    #[test]
+    fn test_flood_fill() {
+        let (border, paint) = (BMPixel(0xff_ffff), BMPixel(0xff_0000));
+        let mut bmp = Bmp::new(7, 7);
+        bmp.draw_rect(
+            BoundingBox {
+                x1: 1,
+                y1: 1,
+                x2: 6,
+                y2: 5,
+            },
+            border,
+        );
+        bmp.flood_fill(3, 2, paint);
+
+        for y in 0..7 {
+            for x in 0..7 {
+                let inside = (2..5).contains(&x) && (2..4).contains(&y);
+                assert_eq!(bmp.get_pixel(x, y) == Some(paint), inside, "({x}, {y})");
+            }
+        }
+        assert_eq!(bmp.get_pixel(1, 1), Some(border));
+
+        let before = bmp.clone();
+        bmp.flood_fill(3, 2, paint);
+        assert_eq!(bmp, before);
+    }
+
+    #[test]
+    fn test_flood_fill_large_region() {
+        let mut bmp = Bmp::new(512, 512);
+        bmp.flood_fill(0, 0, BMPixel(0x12_3456));
+        assert!(bmp.pixels.iter().all(|&pixel| pixel == BMPixel(0x12_3456)));
+    }
+
+    #[test]
+    fn test_auto_deskew() {
+        // Horizontal text-like stripes leaning by 8 degrees
+        let size = 48;
+        let (sin, cos) = 8f32.to_radians().sin_cos();
+        let stripes: Vec<u8> = (0..size * size)
+            .map(|i| {
+                let (x, y) = ((i % size) as f32, (i / size) as f32);
+                let t = (y * cos - x * sin) * 0.7;
+                (128.0 + 100.0 * t.sin()) as u8
+            })
+            .collect();
+        let skewed = Bmp::from_grayscale_bytes(size, size, &stripes).unwrap();
+        assert!(
+            (skewed.skew_angle().abs() - 8.0).abs() < 1.0,
+            "{}",
+            skewed.skew_angle()
+        );
+
+        let straightened = skewed.auto_deskew(BMPixel(0x80_8080));
+        assert_eq!((straightened.width, straightened.height), (size, size));
+        assert!(
+            straightened.skew_angle().abs() < 1.0,
+            "{}",
+            straightened.skew_angle()
+        );
+    }
+
+    #[test]
+    fn test_draw_circle() {
+        let color = BMPixel(0x00_00ff);
+        let count = |bmp: &Bmp| bmp.pixels.iter().filter(|&&pixel| pixel == color).count();
+
+        let mut bmp = Bmp::new(32, 32);
+        bmp.draw_circle(16, 16, 10, color);
+        let area = std::f32::consts::PI * 100.0;
+        assert!(
+            (count(&bmp) as f32 - area).abs() < area * 0.05,
+            "{}",
+            count(&bmp)
+        );
+        assert_eq!(bmp.get_pixel(26, 16), Some(color));
+        assert_eq!(bmp.get_pixel(24, 24), Some(BMPixel::EMPTY));
+
+        let mut bmp = Bmp::new(8, 8);
+        bmp.draw_circle(0, 0, 2, color);
+        assert_eq!(count(&bmp), 6);
+    }
+
+    #[test]
+    fn test_duotone() {
+        let (shadow, highlight) = (BMPixel(0x20_1060), BMPixel(0xff_e080));
+        let mut bmp = Bmp::from_grayscale_bytes(3, 1, &[0, 255, 128]).unwrap();
+        bmp.duotone(shadow, highlight);
+        assert_eq!(bmp.pixels[0], shadow);
+        assert_eq!(bmp.pixels[1], highlight);
+        assert_eq!(bmp.pixels[2], BMPixel(0x90_7870));
+    }
+
+    #[test]
+    fn test_pixel_blend() {
+        let (a, b) = (BMPixel(0x0000_0000), BMPixel(0xff80_40ff));
+        assert_eq!(a.blend(b, 0.0), a);
+        assert_eq!(a.blend(b, 1.0), b);
+        assert_eq!(a.blend(b, 0.5), BMPixel(0x8040_2080));
+    }
+
+    #[test]
+    fn test_rows() {
+        let mut bmp = Bmp {
+            width: 3,
+            height: 2,
+            pixels: (1..=6).map(BMPixel).collect(),
+        };
+        let rows: Vec<&[BMPixel]> = bmp.rows().collect();
+        assert_eq!(rows.len(), 2);
+        assert!(rows.iter().all(|row| row.len() == 3));
+        assert_eq!(rows[1], [4, 5, 6].map(BMPixel));
+
+        for row in bmp.rows_mut() {
+            row.reverse();
+        }
+        assert_eq!(bmp.pixels, [3, 2, 1, 6, 5, 4].map(BMPixel));
+        assert_eq!(Bmp::new(0, 4).rows().count(), 0);
+    }
+
+    #[test]
+    fn test_to_planar() {
+        let bmp =
+            Bmp::read_to_bmp(Path::new("data/greenblue_square-1794933754679872826.bmp")).unwrap();
+        let (red, green, blue) = bmp.to_planar();
+        for plane in [&red, &green, &blue] {
+            assert_eq!(plane.len(), bmp.width * bmp.height);
+        }
+        let recombined: Vec<BMPixel> = (0..red.len())
+            .map(|i| BMPixel::from_rgba(red[i], green[i], blue[i], 0))
+            .collect();
+        assert_eq!(recombined, bmp.pixels);
+
+        // The first byte is the visual top-left: black, above the fixture's blue bottom-left
+        assert_eq!((blue[0], blue[11 * bmp.width]), (0x00, 0xff));
+    }
+
+    #[test]
+    fn test_enumerate_pixels() {
+        let mut bmp = Bmp {
+            width: 2,
+            height: 2,
+            pixels: (1..=4).map(BMPixel).collect(),
+        };
+        let cells: Vec<_> = bmp.enumerate_pixels().collect();
+        assert_eq!(cells.first(), Some(&(0, 0, BMPixel(1))));
+        assert_eq!(cells.last(), Some(&(1, 1, BMPixel(4))));
+
+        for (x, y, pixel) in bmp.enumerate_pixels_mut() {
+            *pixel = BMPixel((x * 10 + y) as u32);
+        }
+        assert_eq!(bmp.pixels, [0, 10, 1, 11].map(BMPixel));
+    }
+
+    #[test]
+    fn test_from_planar() {
+        let bmp =
+            Bmp::read_to_bmp(Path::new("data/greenblue_square-1794933754679872826.bmp")).unwrap();
+        let (red, green, blue) = bmp.to_planar();
+        let rebuilt = Bmp::from_planar(bmp.width, bmp.height, &red, &green, &blue).unwrap();
+        assert_eq!(rebuilt, bmp);
+
+        // The first plane bytes are the visual top row, which a bottom-up file stores last
+        let top_red = Bmp::from_planar(1, 2, &[0xff, 0], &[0, 0], &[0, 0]).unwrap();
+        assert_eq!(top_red.to_bytes()[54..], [0, 0, 0, 0, 0, 0, 0xff, 0]);
+
+        assert!(matches!(
+            Bmp::from_planar(bmp.width, bmp.height, &red, &green[1..], &blue),
+            Err(BmpError::InvalidLength {
+                expected: 144,
+                found: 143
+            })
+        ));
+    }
+
+    #[test]
+    fn test_read_rle8() {
+        let rle = Bmp::read_to_bmp(Path::new("data/rle8.bmp")).unwrap();
+        let uncompressed = Bmp::read_to_bmp(Path::new("data/rle8-uncompressed.bmp")).unwrap();
+        assert_eq!((rle.width, rle.height), (8, 6));
+        assert_eq!(rle, uncompressed);
+        // Pixels skipped by the delta escape fall back to the first palette entry
+        assert_eq!(rle.get_pixel(4, 3), Some(BMPixel(0x00_0000)));
+        assert_eq!(rle.get_pixel(5, 2), Some(BMPixel(0xff_0000)));
+
+        // Without the end-of-bitmap marker the stream is cut short
+        let mut bytes = std::fs::read("data/rle8.bmp").unwrap();
+        bytes.truncate(bytes.len() - 2);
+        let size_image = (bytes.len() - 70) as u32;
+        bytes[34..38].copy_from_slice(&size_image.to_le_bytes());
+        assert!(matches!(
+            Bmp::from_bytes(&bytes),
+            Err(BmpError::TruncatedPixelData)
+        ));
+
+        // A tiny stream must not be able to claim a huge image
+        let mut bytes = std::fs::read("data/rle8.bmp").unwrap();
+        bytes[18..22].copy_from_slice(&200_000u32.to_le_bytes());
+        bytes[22..26].copy_from_slice(&200_000i32.to_le_bytes());
+        assert!(matches!(
+            Bmp::from_bytes(&bytes),
+            Err(BmpError::ImageTooLarge {
+                width: 200_000,
+                height: 200_000
+            })
+        ));
+
+        // A lone end-of-bitmap marker is valid and leaves every pixel at the first palette entry
+        let mut bytes = std::fs::read("data/rle8.bmp").unwrap();
+        bytes.truncate(70);
+        bytes.extend_from_slice(&[0, 1]);
+        bytes[18..22].copy_from_slice(&64u32.to_le_bytes());
+        bytes[22..26].copy_from_slice(&64i32.to_le_bytes());
+        bytes[34..38].copy_from_slice(&2u32.to_le_bytes());
+        let blank = Bmp::from_bytes(&bytes).unwrap();
+        assert_eq!((blank.width, blank.height), (64, 64));
+        assert!(blank
+            .pixels
+            .iter()
+            .all(|&pixel| pixel == BMPixel(0x00_0000)));
+
+        // The same stream is still refused for an image past the pixel cap
+        bytes[18..22].copy_from_slice(&16_384u32.to_le_bytes());
+        bytes[22..26].copy_from_slice(&16_384i32.to_le_bytes());
+        assert!(matches!(
+            Bmp::from_bytes(&bytes),
+            Err(BmpError::ImageTooLarge {
+                width: 16_384,
+                height: 16_384
+            })
+        ));
+    }
+
+    #[test]
+    fn test_fill_radial_gradient() {
+        let (inner, outer) = (BMPixel(0xff_ffff), BMPixel(0x20_0040));
+        let mut bmp = Bmp::new(21, 21);
+        bmp.fill_radial_gradient(10, 10, 8, inner, outer);
+        assert_eq!(bmp.get_pixel(10, 10), Some(inner));
+        assert_eq!(bmp.get_pixel(18, 10), Some(outer));
+        assert_eq!(bmp.get_pixel(10, 2), Some(outer));
+        assert_eq!(bmp.get_pixel(0, 0), Some(outer));
+
+        let halfway = bmp.get_pixel(14, 10).unwrap();
+        assert_eq!(halfway, inner.blend(outer, 0.5));
+        assert!(bmp.get_pixel(12, 10).unwrap().red() > halfway.red());
+    }
+
+    #[test]
+    fn test_read_paletted_8bit() {
+        let bmp = Bmp::read_to_bmp(Path::new("data/paletted8.bmp")).unwrap();
+        assert_eq!((bmp.width, bmp.height), (5, 3));
+        assert_eq!(bmp.get_pixel(0, 2), Some(BMPixel(0x00_0000)));
+        assert_eq!(bmp.get_pixel(2, 2), Some(BMPixel(0xc8_1e0a)));
+        assert_eq!(bmp.get_pixel(4, 2), Some(BMPixel(0x5a_c83c)));
+        assert_eq!(bmp.get_pixel(1, 1), Some(BMPixel(0x0f_78f0)));
+        assert_eq!(bmp.get_pixel(4, 0), Some(BMPixel(0xff_ffff)));
+
+        // The indexed writer's output reads back to the same pixels
+        let palette: Vec<BMPixel> = (0..200).map(|i| BMPixel(i * 0x01_0203)).collect();
+        let mut bmp = Bmp::new(7, 3);
+        for (i, pixel) in bmp.pixels.iter_mut().enumerate() {
+            *pixel = palette[i * 9];
+        }
+        let bytes = Ode5Bmp::new_indexed(&bmp, &palette, 8).to_bytes();
+        assert_eq!(Bmp::from_bytes(&bytes).unwrap(), bmp);
+    }
+
+    #[test]
+    fn test_verify_roundtrip() {
+        let mut bmp = Bmp::new(16, 12);
+        bmp.fill_radial_gradient(8, 6, 7, BMPixel(0xff_e080), BMPixel(0x10_2040));
+        bmp.draw_circle(4, 4, 3, BMPixel(0x00_ff00));
+        assert!(bmp.verify_roundtrip());
+
+        // Alpha is not part of the 24-bit format, so opaque and translucent pixels still pass
+        bmp.set_pixel(2, 2, BMPixel::from_rgb(0x12, 0x34, 0x56));
+        bmp.set_pixel(3, 3, BMPixel(0x8000_ff00));
+        assert!(bmp.verify_roundtrip());
+    }
+
+    #[test]
+    fn test_write_to_file_indexed() {
+        let colors = [0xff_0000, 0x00_ff00, 0x00_00ff, 0x12_3456].map(BMPixel);
+        let mut bmp = Bmp::new(5, 3);
+        for (i, pixel) in bmp.pixels.iter_mut().enumerate() {
+            *pixel = colors[i % 4];
+        }
+        let path = std::env::temp_dir().join("ode5bmp-indexed.bmp");
+        bmp.write_to_file_indexed(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(u16::from_le_bytes([bytes[28], bytes[29]]), 8);
+        assert_eq!(Bmp::read_to_bmp(&path).unwrap(), bmp);
+
+        let mut many = Bmp::new(16, 17);
+        for (i, pixel) in many.pixels.iter_mut().enumerate() {
+            *pixel = BMPixel(i as u32);
+        }
+        assert!(matches!(
+            many.write_to_file_indexed(&path),
+            Err(BmpError::TooManyColors { limit: 256 })
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_extract_tile() {
+        let bmp = Bmp {
+            width: 4,
+            height: 2,
+            pixels: (0..8).map(BMPixel).collect(),
+        };
+        let tile = bmp.extract_tile(2, 1, 4, 2);
+        assert_eq!((tile.width, tile.height), (4, 2));
+        assert_eq!(tile.pixels, [6, 7, 4, 5, 2, 3, 0, 1].map(BMPixel));
+
+        let tile = bmp.extract_tile(-1, -3, 2, 1);
+        assert_eq!(tile.pixels, [7, 4].map(BMPixel));
+    }
+
+    #[test]
+    fn test_read_bmp_size_image_validation() {
+        let bmp =
+            Bmp::read_to_bmp(Path::new("data/greenblue_square-1794933754679872826.bmp")).unwrap();
+        let mut bytes = bmp.to_bytes();
+
+        bytes[34..38].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            Bmp::from_bytes(&bytes),
+            Err(BmpError::SizeMismatch {
+                declared,
+                expected: 432
+            }) if declared == u32::MAX as usize
+        ));
+        // The progress reader validates the header the same way
+        let path = std::env::temp_dir().join("ode5bmp-size-image.bmp");
+        std::fs::write(&path, &bytes).unwrap();
+        let progress = Bmp::read_to_bmp_with_progress(&path, |_| {});
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            progress,
+            Err(BmpError::SizeMismatch {
+                declared,
+                expected: 432
+            }) if declared == u32::MAX as usize
+        ));
+
+        bytes[34..38].copy_from_slice(&0u32.to_le_bytes());
+        assert_eq!(Bmp::from_bytes(&bytes).unwrap(), bmp);
+
+        // Dimensions whose size overflows must fail cleanly rather than wrap or panic
+        let mut huge = bytes.clone();
+        huge[18..22].copy_from_slice(&u32::MAX.to_le_bytes());
+        huge[22..26].copy_from_slice(&i32::MAX.to_le_bytes());
+        assert!(matches!(
+            Bmp::from_bytes(&huge),
+            Err(BmpError::TruncatedPixelData)
+        ));
+        huge[34..38].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            Bmp::from_bytes(&huge),
+            Err(BmpError::TruncatedPixelData)
+        ));
+
+        let mut paletted = std::fs::read("data/paletted8.bmp").unwrap();
+        paletted[18..22].copy_from_slice(&u32::MAX.to_le_bytes());
+        paletted[22..26].copy_from_slice(&i32::MIN.to_le_bytes());
+        assert!(matches!(
+            Bmp::from_bytes(&paletted),
+            Err(BmpError::TruncatedPixelData)
+        ));
+    }
+
+    #[test]
+    fn test_dither_ordered() {
+        let (black, white) = (BMPixel(0x00_0000), BMPixel(0xff_ffff));
+        let mut bmp = Bmp::from_grayscale_bytes(8, 8, &[128; 64]).unwrap();
+        bmp.dither_ordered(&[black, white]);
+        for (x, y, pixel) in bmp.enumerate_pixels() {
+            let expected = if (x + y) % 2 == 1 { white } else { black };
+            assert_eq!(pixel, expected, "({x}, {y})");
+        }
+
+        let mut dark = Bmp::from_grayscale_bytes(4, 4, &[32; 16]).unwrap();
+        dark.dither_ordered(&[black, white]);
+        let lit = dark.pixels.iter().filter(|&&pixel| pixel == white).count();
+        assert_eq!(lit, 2);
+    }
+
+    #[test]
+    fn test_read_top_down_bmp() {
+        let bottom_up =
+            Bmp::read_to_bmp(Path::new("data/greenblue_square-1794933754679872826.bmp")).unwrap();
+        let top_down = Bmp::read_to_bmp(Path::new("data/greenblue_square-top-down.bmp")).unwrap();
+        assert_eq!((top_down.width, top_down.height), (12, 12));
+        assert_eq!(top_down, bottom_up);
+    }
+
+    #[test]
+    fn test_row_order_matches_disk() {
+        // The fixture is black at its visual top-left and blue at its bottom-left
+        let path = Path::new("data/greenblue_square-1794933754679872826.bmp");
+        let bmp = Bmp::read_to_bmp(path).unwrap();
+        assert_eq!(bmp.get_pixel(0, 0), Some(BMPixel(0x00_0000)));
+        assert_eq!(bmp.get_pixel(0, 11), Some(BMPixel(0x00_00ff)));
+        assert_eq!(Bmp::read_to_bmp_with_progress(path, |_| {}).unwrap(), bmp);
+        // A clockwise turn brings the bottom-left corner to the top-left
+        assert_eq!(bmp.rotate90().get_pixel(0, 0), Some(BMPixel(0x00_00ff)));
+
+        // Row 0 is the visual top, so it is written last in a bottom-up file
+        let mut bmp = Bmp::new(2, 3);
+        bmp.set_pixel(0, 0, BMPixel(0xff_0000));
+        bmp.set_pixel(1, 0, BMPixel(0xff_0000));
+        let bytes = bmp.to_bytes();
+        assert_eq!(bytes[54..60], [0; 6]);
+        assert_eq!(
+            bytes[bytes.len() - 8..bytes.len() - 2],
+            [0, 0, 0xff, 0, 0, 0xff]
+        );
+        for bytes in [
+            Ode5Bmp::new_indexed(&bmp, &bmp.distinct_colors(256), 8).to_bytes(),
+            Ode5Bmp::new_bgra(&bmp).to_bytes_v5(),
+        ] {
+            assert_eq!(Bmp::from_bytes(&bytes).unwrap(), bmp);
+        }
+    }
+
+    #[test]
+    fn test_clone_stamp() {
+        let patch = BMPixel(0xff_00ff);
+        let mut bmp = Bmp::new(12, 8);
+        bmp.draw_circle(2, 2, 2, patch);
+        bmp.set_pixel(2, 2, BMPixel(0x12_3456));
+
+        bmp.clone_stamp(2, 2, 8, 4, 2);
+        assert_eq!(bmp.get_pixel(8, 4), Some(BMPixel(0x12_3456)));
+        for (dx, dy) in [(-2, 0), (2, 0), (0, -2), (0, 2), (1, 1)] {
+            let (x, y) = ((8 + dx) as usize, (4 + dy) as usize);
+            assert_eq!(bmp.get_pixel(x, y), Some(patch), "({x}, {y})");
+        }
+        // Outside the circular mask nothing changes
+        assert_eq!(bmp.get_pixel(10, 6), Some(BMPixel::EMPTY));
+        // The source is left alone
+        assert_eq!(bmp.get_pixel(2, 2), Some(BMPixel(0x12_3456)));
+
+        // A destination hanging off the corner is clipped rather than panicking
+        bmp.clone_stamp(2, 2, 11, 0, 2);
+        assert_eq!(bmp.get_pixel(11, 0), Some(BMPixel(0x12_3456)));
+        assert_eq!(bmp.get_pixel(10, 0), Some(patch));
+    }
+
+    #[rstest]
+    #[case(3, 2)]
+    #[case(5, 3)]
+    #[case(7, 1)]
+    fn test_roundtrip_padded_rows(#[case] width: usize, #[case] height: usize) {
+        let mut bmp = Bmp::new(width, height);
+        for (i, pixel) in bmp.pixels.iter_mut().enumerate() {
+            *pixel = BMPixel((i as u32 + 1) * 0x03_0507);
+        }
+        let path = std::env::temp_dir().join(format!("ode5bmp-padded-{width}x{height}.bmp"));
+        bmp.write_to_file(&path).unwrap();
+        assert_eq!(Bmp::read_to_bmp(&path).unwrap(), bmp);
+        assert!(bmp.verify_roundtrip());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_ssim_map() {
+        let bmp =
+            Bmp::read_to_bmp(Path::new("data/greenblue_square-1794933754679872826.bmp")).unwrap();
+        let map = bmp.ssim_map(&bmp).unwrap();
+        assert_eq!((map.width, map.height), (bmp.width, bmp.height));
+        assert!(map.pixels.iter().all(|&pixel| pixel == BMPixel(0xff_ffff)));
+
+        let mut damaged = bmp.clone();
+        damaged.invert();
+        damaged.pixels[..bmp.width * 6].copy_from_slice(&bmp.pixels[..bmp.width * 6]);
+        let map = bmp.ssim_map(&damaged).unwrap();
+        assert_eq!(map.get_pixel(0, 0), Some(BMPixel(0xff_ffff)));
+        assert!(map.get_pixel(11, 11).unwrap().red() < 128);
+
+        assert!(matches!(
+            bmp.ssim_map(&Bmp::new(3, 3)),
+            Err(BmpError::DimensionMismatch { .. })
+        ));
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn test_image_rgb_conversion() {
+        let mut rgb = image::RgbImage::new(3, 2);
+        rgb.put_pixel(2, 0, image::Rgb([0xff, 0x00, 0x00]));
+        rgb.put_pixel(0, 1, image::Rgb([0x12, 0x34, 0x56]));
+
+        let bmp = Bmp::from(rgb.clone());
+        assert_eq!((bmp.width, bmp.height), (3, 2));
+        assert_eq!(bmp.get_pixel(2, 0), Some(BMPixel(0xff_0000)));
+        assert_eq!(bmp.get_pixel(0, 1), Some(BMPixel(0x12_3456)));
+        assert_eq!(image::RgbImage::from(&bmp), rgb);
+
+        // A file read from disk converts the right way up, and writes back the same way
+        let path = Path::new("data/greenblue_square-1794933754679872826.bmp");
+        let rgb = image::RgbImage::from(&Bmp::read_to_bmp(path).unwrap());
+        assert_eq!(rgb.get_pixel(0, 0), &image::Rgb([0x00, 0x00, 0x00]));
+        assert_eq!(rgb.get_pixel(0, 11), &image::Rgb([0x00, 0x00, 0xff]));
+        assert_eq!(Bmp::from(rgb).to_bytes(), std::fs::read(path).unwrap());
+    }
+
+    #[rstest]
+    #[case("data/france-7921693104947760092.bmp", 20)]
+    #[case("data/handcrafted-2044735835957623026.bmp", 5)]
+    #[case("data/paletted8.bmp", 3)]
+    #[case("data/greenblue_square-top-down.bmp", 12)]
+    #[case("data/rle8.bmp", 1)]
+    fn test_read_to_bmp_with_progress(#[case] file_path: &str, #[case] calls: usize) {
+        let path = Path::new(file_path);
+        let mut fractions = Vec::new();
+        let bmp =
+            Bmp::read_to_bmp_with_progress(path, |fraction| fractions.push(fraction)).unwrap();
+        assert_eq!(bmp, Bmp::read_to_bmp(path).unwrap());
+        assert_eq!(fractions.len(), calls);
+        assert!(fractions.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(fractions.last(), Some(&1.0));
+    }
+
+    #[test]
+    fn test_read_to_bmp_with_progress_errors() {
+        let not_bmp = Bmp::read_to_bmp_with_progress(Path::new("data/not-a-bmp.txt"), |_| {});
+        assert!(matches!(not_bmp, Err(BmpError::NotBmp)));
+
+        let bytes = std::fs::read("data/handcrafted-2044735835957623026.bmp").unwrap();
+        let path = std::env::temp_dir().join("ode5bmp-truncated-progress.bmp");
+        std::fs::write(&path, &bytes[..54 + 50]).unwrap();
+        let mut last = 0.0;
+        let truncated = Bmp::read_to_bmp_with_progress(&path, |fraction| last = fraction);
+        assert!(matches!(truncated, Err(BmpError::TruncatedPixelData)));
+        assert!(last < 1.0);
+
+        // Header fields that claim more than the file holds fail before anything is allocated
+        let mut huge = bytes.clone();
+        huge[18..22].copy_from_slice(&u32::MAX.to_le_bytes());
+        huge[22..26].copy_from_slice(&i32::MAX.to_le_bytes());
+        std::fs::write(&path, &huge).unwrap();
+        let huge_dimensions = Bmp::read_to_bmp_with_progress(&path, |_| {});
+        assert!(matches!(huge_dimensions, Err(BmpError::TruncatedPixelData)));
+
+        let mut far_offset = bytes.clone();
+        far_offset[10..14].copy_from_slice(&u32::MAX.to_le_bytes());
+        std::fs::write(&path, &far_offset).unwrap();
+        let offset = Bmp::read_to_bmp_with_progress(&path, |_| {});
+        assert!(matches!(offset, Err(BmpError::TruncatedPixelData)));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_offset_inside_headers() {
+        let mut bytes = std::fs::read("data/handcrafted-2044735835957623026.bmp").unwrap();
+        bytes[10..14].copy_from_slice(&20u32.to_le_bytes());
+        let path = std::env::temp_dir().join("ode5bmp-offset-inside-headers.bmp");
+        std::fs::write(&path, &bytes).unwrap();
+        let progress = Bmp::read_to_bmp_with_progress(&path, |_| {});
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(progress, Err(BmpError::NotBmp)));
+        assert!(matches!(Bmp::from_bytes(&bytes), Err(BmpError::NotBmp)));
     }
 }
@@ -1,7 +1,10 @@
 #![allow(non_snake_case)]
 
+use std::collections::HashMap;
+
 use crate::{
-    helpers::{calculate_image_size, calculate_row_length},
+    error::BmpError,
+    helpers::{calculate_image_size, calculate_row_length, calculate_stride},
     models::{BMPixel, Bmp},
 };
 
@@ -10,6 +13,7 @@ use crate::{
 pub struct Ode5Bmp {
     file_header: FileHeader,
     info_header: InfoHeader,
+    palette: Vec<BMPixel>,
     data: Vec<u8>,
 }
 
@@ -20,6 +24,7 @@ impl Default for Ode5Bmp {
         Self {
             file_header,
             info_header,
+            palette: Vec::new(),
             data: Vec::new(),
         }
     }
@@ -37,48 +42,224 @@ impl Ode5Bmp {
         ode5bmp
     }
 
+    /// Encodes `bmp` as 1-, 4- or 8-bit indices into `palette`, which must hold every RGB value.
+    pub fn new_indexed(bmp: &Bmp, palette: &[BMPixel], bit_count: u16) -> Self {
+        let indices: HashMap<u32, u8> = palette
+            .iter()
+            .enumerate()
+            .map(|(index, pixel)| (pixel.0 & 0x00ff_ffff, index as u8))
+            .collect();
+        let mut ode5bmp = Self::default()
+            .with_dimensions(bmp.width, bmp.height)
+            .with_bit_count(bit_count, palette.len());
+        ode5bmp.palette = palette.to_vec();
+
+        let stride = calculate_stride(bmp.width, bit_count);
+        let bits = bit_count as usize;
+        ode5bmp.data.resize(stride * bmp.height, 0);
+        for (y, row) in bmp.rows().rev().enumerate() {
+            for (x, pixel) in row.iter().enumerate() {
+                let index = indices[&(pixel.0 & 0x00ff_ffff)];
+                // Pixels are packed most significant bits first
+                let bit = x * bits;
+                ode5bmp.data[y * stride + bit / 8] |= index << (8 - bits - bit % 8);
+            }
+        }
+        ode5bmp
+    }
+
+    /// Encodes `bmp` as 32-bit BGRA, keeping the top byte of each pixel as alpha. Only
+    /// [`Ode5Bmp::to_bytes_v5`] declares that byte as alpha to other readers.
+    pub fn new_bgra(bmp: &Bmp) -> Self {
+        let mut ode5bmp = Self::default()
+            .with_dimensions(bmp.width, bmp.height)
+            .with_bit_count(32, 0);
+        for pixel in bmp.rows().rev().flatten() {
+            ode5bmp.data.extend_from_slice(&[
+                pixel.blue(),
+                pixel.green(),
+                pixel.red(),
+                pixel.alpha(),
+            ]);
+        }
+        ode5bmp
+    }
+
+    fn with_bit_count(mut self, bit_count: u16, palette_len: usize) -> Self {
+        let width = self.info_header.biWidth as usize;
+        let height = self.info_header.biHeight.unsigned_abs() as usize;
+        let bi_size_img = calculate_stride(width, bit_count) * height;
+        let offset =
+            std::mem::size_of::<FileHeader>() + std::mem::size_of::<InfoHeader>() + palette_len * 4;
+        self.file_header.bfOffBits = offset as u32;
+        self.file_header.bfSize = (offset + bi_size_img) as u32;
+        self.info_header.biBitCount = bit_count;
+        self.info_header.biSizeImage = bi_size_img as u32;
+        self.info_header.biClrUsed = palette_len as u32;
+        self
+    }
+
     fn with_dimensions(mut self, width: usize, height: usize) -> Self {
         let bi_size_img = calculate_image_size(width, height);
         let file_size =
             std::mem::size_of::<FileHeader>() + std::mem::size_of::<InfoHeader>() + bi_size_img;
         self.file_header.bfSize = file_size as u32;
         self.info_header.biWidth = width as u32;
-        self.info_header.biHeight = height as u32;
+        self.info_header.biHeight = height as i32;
         self.info_header.biSizeImage = bi_size_img as u32;
-        // Grow the data vector
-        self.data.resize(bi_size_img, 0);
         self
     }
 
-    // We need to revert RGB to BGR
+    // We need to revert RGB to BGR, and the top-down rows to the file's bottom-up order
     fn with_pixels(mut self, pixels: &[BMPixel]) -> Self {
-        let row_length = calculate_row_length(self.info_header.biWidth as usize);
-        // Each row must be padded to 4 bytes
-        for y in (0..self.info_header.biHeight).rev() {
-            for x in 0..self.info_header.biWidth {
-                let index = (y * row_length as u32 + x * 3) as usize;
-                self.data[index] = pixels[(y * self.info_header.biWidth + x) as usize].blue();
-                self.data[index + 1] = pixels[(y * self.info_header.biWidth + x) as usize].green();
-                self.data[index + 2] = pixels[(y * self.info_header.biWidth + x) as usize].red();
-            }
+        let width = self.info_header.biWidth as usize;
+        let height = self.info_header.biHeight.unsigned_abs() as usize;
+        let row_length = calculate_row_length(width);
+        // Grow the data vector
+        self.data.resize(self.info_header.biSizeImage as usize, 0);
+        for y in 0..height {
+            let source = height - 1 - y;
+            let row = encode_row(&pixels[source * width..(source + 1) * width]);
+            self.data[y * row_length..(y + 1) * row_length].copy_from_slice(&row);
         }
         self
     }
 
+    /// Encodes `bmp` into `buf`, replacing its contents but reusing its allocation.
+    pub fn encode_into(bmp: &Bmp, buf: &mut Vec<u8>) {
+        let headers = Self::default().with_dimensions(bmp.width, bmp.height);
+        buf.clear();
+        buf.reserve(headers.file_header.bfSize as usize);
+        buf.extend_from_slice(&headers.file_header.to_bytes());
+        buf.extend_from_slice(&headers.info_header.to_bytes());
+        for row in bmp.rows().rev() {
+            push_row(row, buf);
+        }
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
         bytes.extend_from_slice(&self.file_header.to_bytes());
         bytes.extend_from_slice(&self.info_header.to_bytes());
+        for color in &self.palette {
+            bytes.extend_from_slice(&[color.blue(), color.green(), color.red(), 0]);
+        }
         bytes.extend_from_slice(&self.data);
         bytes
     }
+
+    /// Encodes the bitmap with a BITMAPV5HEADER tagged as sRGB instead of the 40-byte header.
+    pub fn to_bytes_v5(&self) -> Vec<u8> {
+        let extra = (std::mem::size_of::<V5Header>() - std::mem::size_of::<InfoHeader>()) as u32;
+        let file_header = FileHeader {
+            bfSize: self.file_header.bfSize + extra,
+            bfOffBits: self.file_header.bfOffBits + extra,
+            ..FileHeader::new(0)
+        };
+        let v5_header = V5Header::new(&self.info_header);
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&file_header.to_bytes());
+        bytes.extend_from_slice(&v5_header.to_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+}
+
+/// Encodes one row of pixels as BGR triplets, padded to 4 bytes.
+pub(crate) fn encode_row(row: &[BMPixel]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(calculate_row_length(row.len()));
+    push_row(row, &mut bytes);
+    bytes
+}
+
+fn push_row(row: &[BMPixel], bytes: &mut Vec<u8>) {
+    for pixel in row {
+        bytes.extend_from_slice(&[pixel.blue(), pixel.green(), pixel.red()]);
+    }
+    let padding = calculate_row_length(row.len()) - row.len() * 3;
+    bytes.extend(std::iter::repeat_n(0, padding));
+}
+
+/// `biCompression` for 8-bit run-length encoded pixel data.
+pub(crate) const BI_RLE8: u32 = 1;
+
+/// `biCompression` for pixels described by channel masks after the info header.
+pub(crate) const BI_BITFIELDS: u32 = 3;
+
+/// Red, green, blue and alpha masks of 32-bit BGRA pixels.
+pub(crate) const BGRA_MASKS: [u32; 4] = [0x00ff_0000, 0x0000_ff00, 0x0000_00ff, 0xff00_0000];
+
+/// Decodes the BGR0 color table stored between the headers and the pixel data.
+pub(crate) fn decode_palette(bytes: &[u8], len: usize) -> Vec<BMPixel> {
+    bytes
+        .chunks_exact(4)
+        .take(len)
+        .map(|entry| BMPixel::from_rgba(entry[2], entry[1], entry[0], 0))
+        .collect()
+}
+
+/// Upper bound on the pixels an RLE8 image may declare. Compressed data can be tiny while the
+/// header claims a huge image, so the buffer size cannot be checked against the file length.
+pub(crate) const MAX_RLE8_PIXELS: usize = 1 << 24;
+
+/// Expands BI_RLE8 data into one palette index per pixel, rows in bottom-up file order. Pixels skipped by
+/// a delta, an early end of line, or an early end of bitmap keep index 0.
+pub(crate) fn decode_rle8(data: &[u8], width: usize, height: usize) -> Result<Vec<u8>, BmpError> {
+    let len = width
+        .checked_mul(height)
+        .filter(|&len| len <= MAX_RLE8_PIXELS)
+        .ok_or(BmpError::ImageTooLarge { width, height })?;
+    let mut indices = vec![0u8; len];
+    let mut put = |x: usize, y: usize, index: u8| {
+        if x < width && y < height {
+            indices[y * width + x] = index;
+        }
+    };
+    let mut bytes = data.iter().copied();
+    let mut next = || bytes.next().ok_or(BmpError::TruncatedPixelData);
+    let (mut x, mut y) = (0, 0);
+    loop {
+        match (next()?, next()?) {
+            // End of line
+            (0, 0) => {
+                x = 0;
+                y += 1;
+            }
+            // End of bitmap
+            (0, 1) => break,
+            // Delta: skip right and down
+            (0, 2) => {
+                x += next()? as usize;
+                y += next()? as usize;
+            }
+            // Absolute mode: literal indices, padded to an even byte count
+            (0, count) => {
+                for _ in 0..count {
+                    put(x, y, next()?);
+                    x += 1;
+                }
+                if count % 2 == 1 {
+                    next()?;
+                }
+            }
+            // Encoded mode: a run of one index
+            (count, index) => {
+                for _ in 0..count {
+                    put(x, y, index);
+                    x += 1;
+                }
+            }
+        }
+    }
+    Ok(indices)
 }
 
 #[derive(Debug)]
-#[repr(packed)]
+#[repr(C, packed)]
 pub struct FileHeader {
     pub(crate) _bfType: [u8; 2],
-    bfSize: u32,
+    pub(crate) bfSize: u32,
     _bfReserved1: u16,
     _bfReserved2: u16,
     pub(crate) bfOffBits: u32,
@@ -121,12 +302,13 @@ impl FileHeader {
     }
 }
 
-#[derive(Debug)]
-#[repr(packed)]
+#[derive(Debug, Clone)]
+#[repr(C, packed)]
 pub struct InfoHeader {
-    biSize: u32,
+    pub(crate) biSize: u32,
     pub(crate) biWidth: u32,
-    pub(crate) biHeight: u32,
+    /// Negative for top-down row order.
+    pub(crate) biHeight: i32,
     biPlanes: u16,
     pub(crate) biBitCount: u16,
     pub(crate) biCompression: u32,
@@ -156,6 +338,14 @@ impl Default for InfoHeader {
 }
 
 impl InfoHeader {
+    /// Number of color table entries, defaulting to the full range for indexed bit counts.
+    pub(crate) fn palette_len(&self) -> usize {
+        match self.biClrUsed {
+            0 if self.biBitCount <= 8 => 1 << self.biBitCount,
+            used => used as usize,
+        }
+    }
+
     fn to_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
         bytes.extend_from_slice(&self.biSize.to_le_bytes());
@@ -175,7 +365,7 @@ impl InfoHeader {
     pub(crate) fn from_bytes(bytes: &[u8]) -> Self {
         let biSize = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
         let biWidth = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
-        let biHeight = u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]);
+        let biHeight = i32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]);
         let biPlanes = u16::from_le_bytes([bytes[12], bytes[13]]);
         let biBitCount = u16::from_le_bytes([bytes[14], bytes[15]]);
         let biCompression = u32::from_le_bytes([bytes[16], bytes[17], bytes[18], bytes[19]]);
@@ -199,3 +389,78 @@ impl InfoHeader {
         }
     }
 }
+
+/// LCS_sRGB, the `bV5CSType` tag for the sRGB color space.
+const LCS_SRGB: u32 = u32::from_be_bytes(*b"sRGB");
+/// LCS_GM_IMAGES, the perceptual rendering intent.
+const LCS_GM_IMAGES: u32 = 4;
+
+/// The 124-byte BITMAPV5HEADER, which extends the info header with color management fields.
+#[derive(Debug)]
+#[repr(C)]
+pub struct V5Header {
+    info_header: InfoHeader,
+    bV5RedMask: u32,
+    bV5GreenMask: u32,
+    bV5BlueMask: u32,
+    bV5AlphaMask: u32,
+    bV5CSType: u32,
+    bV5Endpoints: [u32; 9], // CIEXYZTRIPLE, unused for sRGB
+    bV5GammaRed: u32,
+    bV5GammaGreen: u32,
+    bV5GammaBlue: u32,
+    bV5Intent: u32,
+    bV5ProfileData: u32,
+    bV5ProfileSize: u32,
+    _bV5Reserved: u32,
+}
+
+impl V5Header {
+    fn new(info_header: &InfoHeader) -> Self {
+        // 32-bit pixels are described by masks so the top byte is read as alpha
+        let (compression, [red, green, blue, alpha]) = match info_header.biBitCount {
+            32 => (BI_BITFIELDS, BGRA_MASKS),
+            _ => (info_header.biCompression, [0; 4]),
+        };
+        Self {
+            info_header: InfoHeader {
+                biSize: std::mem::size_of::<V5Header>() as u32,
+                biCompression: compression,
+                ..info_header.clone()
+            },
+            bV5RedMask: red,
+            bV5GreenMask: green,
+            bV5BlueMask: blue,
+            bV5AlphaMask: alpha,
+            bV5CSType: LCS_SRGB,
+            bV5Endpoints: [0; 9],
+            bV5GammaRed: 0,
+            bV5GammaGreen: 0,
+            bV5GammaBlue: 0,
+            bV5Intent: LCS_GM_IMAGES,
+            bV5ProfileData: 0,
+            bV5ProfileSize: 0,
+            _bV5Reserved: 0,
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.info_header.to_bytes();
+        bytes.extend_from_slice(&self.bV5RedMask.to_le_bytes());
+        bytes.extend_from_slice(&self.bV5GreenMask.to_le_bytes());
+        bytes.extend_from_slice(&self.bV5BlueMask.to_le_bytes());
+        bytes.extend_from_slice(&self.bV5AlphaMask.to_le_bytes());
+        bytes.extend_from_slice(&self.bV5CSType.to_le_bytes());
+        for endpoint in self.bV5Endpoints {
+            bytes.extend_from_slice(&endpoint.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.bV5GammaRed.to_le_bytes());
+        bytes.extend_from_slice(&self.bV5GammaGreen.to_le_bytes());
+        bytes.extend_from_slice(&self.bV5GammaBlue.to_le_bytes());
+        bytes.extend_from_slice(&self.bV5Intent.to_le_bytes());
+        bytes.extend_from_slice(&self.bV5ProfileData.to_le_bytes());
+        bytes.extend_from_slice(&self.bV5ProfileSize.to_le_bytes());
+        bytes.extend_from_slice(&self._bV5Reserved.to_le_bytes());
+        bytes
+    }
+}